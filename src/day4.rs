@@ -1,17 +1,22 @@
 #![allow(dead_code)]
 #![allow(clippy::ptr_arg)]
 
+use anyhow::Result;
 use std::collections::HashMap;
+use unicode_segmentation::UnicodeSegmentation;
+
+// one grapheme cluster per cell, so combining marks and wide glyphs stay aligned
+type Grid<'a> = Vec<Vec<&'a str>>;
 
 fn find_word_in_grid(
-    grid: &Vec<Vec<char>>,
+    grid: &Grid,
     word: &str,
     mut x: isize,
     mut y: isize,
     movement: (isize, isize),
 ) -> bool {
-    for c in word.chars() {
-        if x >= width(grid) || y >= height(grid) || x < 0 || y < 0 || char_at(grid, x, y) != c {
+    for c in word.graphemes(true) {
+        if x >= width(grid) || y >= height(grid) || x < 0 || y < 0 || cell_at(grid, x, y) != c {
             return false;
         }
         x += movement.0;
@@ -20,20 +25,31 @@ fn find_word_in_grid(
     true
 }
 
-fn char_at(grid: &Vec<Vec<char>>, x: isize, y: isize) -> char {
+fn cell_at<'a>(grid: &Grid<'a>, x: isize, y: isize) -> &'a str {
     grid[y as usize][x as usize]
 }
 
-fn width(grid: &Vec<Vec<char>>) -> isize {
-    grid[0].len() as isize
+fn width(grid: &Grid) -> isize {
+    grid.first().map_or(0, |row| row.len()) as isize
 }
 
-fn height(grid: &Vec<Vec<char>>) -> isize {
+fn height(grid: &Grid) -> isize {
     grid.len() as isize
 }
 
-fn to_grid(input: &str) -> Vec<Vec<char>> {
-    input.lines().map(|l| l.chars().collect()).collect()
+fn to_grid(input: &str) -> Result<Grid<'_>> {
+    let grid: Grid = input.lines().map(|l| l.graphemes(true).collect()).collect();
+    for (y, row) in grid.iter().enumerate() {
+        if row.len() as isize != width(&grid) {
+            anyhow::bail!(
+                "row {} has width {}, expected {} (the width of row 0)",
+                y,
+                row.len(),
+                width(&grid)
+            );
+        }
+    }
+    Ok(grid)
 }
 
 const ALL_DIRECTIONS: &[(isize, isize)] = &[
@@ -49,8 +65,8 @@ const ALL_DIRECTIONS: &[(isize, isize)] = &[
 
 const DIAGONAL_DIRECTIONS: &[(isize, isize)] = &[(1, -1), (1, 1), (-1, 1), (-1, -1)];
 
-fn count(input: &str, word: &str) -> usize {
-    let grid = to_grid(input);
+fn count(input: &str, word: &str) -> Result<usize> {
+    let grid = to_grid(input)?;
     let mut count = 0_usize;
     for y in 0..height(&grid) {
         for x in 0..width(&grid) {
@@ -59,11 +75,11 @@ fn count(input: &str, word: &str) -> usize {
             }
         }
     }
-    count
+    Ok(count)
 }
 
-fn count_cross(input: &str, word: &str) -> usize {
-    let grid = to_grid(input);
+fn count_cross(input: &str, word: &str) -> Result<usize> {
+    let grid = to_grid(input)?;
     let mut positions = HashMap::new();
     for y in 0..height(&grid) {
         for x in 0..width(&grid) {
@@ -76,11 +92,36 @@ fn count_cross(input: &str, word: &str) -> usize {
             }
         }
     }
-    positions.iter().filter(|&(_, &c)| c == 2).count()
+    Ok(positions.iter().filter(|&(_, &c)| c == 2).count())
+}
+
+#[test]
+fn test_unicode_grid() -> Result<()> {
+    // "e\u{301}" is an e followed by a combining acute accent: one cell, two chars
+    let data = "Ae\u{301}Ö\nxyå\nÖe\u{301}A";
+    let grid = to_grid(data)?;
+    assert_eq!(width(&grid), 3);
+    assert_eq!(height(&grid), 3);
+    assert_eq!(cell_at(&grid, 1, 0), "e\u{301}");
+    assert_eq!(cell_at(&grid, 2, 1), "å");
+    assert!(find_word_in_grid(&grid, "Ae\u{301}Ö", 0, 0, (1, 0)));
+    assert!(find_word_in_grid(&grid, "Ae\u{301}Ö", 2, 2, (-1, 0)));
+    assert!(!find_word_in_grid(&grid, "Ae\u{301}Ö", 1, 0, (1, 0)));
+    assert_eq!(count(data, "Ae\u{301}Ö")?, 2);
+    assert_eq!(count(data, "Ae")?, 0);
+    assert_eq!(count("日本語\n本日本\n語本日", "日本")?, 8);
+
+    let err = to_grid("XMAS\nXMA\nXMAS").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "row 1 has width 3, expected 4 (the width of row 0)"
+    );
+    assert!(count("XMAS\nXMA", "XMAS").is_err());
+    Ok(())
 }
 
 #[test]
-fn day() {
+fn day() -> Result<()> {
     let data1 = r#"
 MMMSXXMASM
 MSAMXMSMSA
@@ -95,10 +136,11 @@ MXMXAXMASX
     "#
     .trim();
     let data2 = std::fs::read_to_string("input/day4").unwrap();
-    assert!(find_word_in_grid(&to_grid(data1), "XMAS", 4, 0, (1, 1)));
-    assert!(find_word_in_grid(&to_grid(data1), "XMAS", 5, 9, (1, 0)));
-    assert_eq!(count(data1, "XMAS"), 18);
-    assert_eq!(count(&data2, "XMAS"), 2344);
-    assert_eq!(count_cross(data1, "MAS"), 9);
-    assert_eq!(count_cross(&data2, "MAS"), 1815);
+    assert!(find_word_in_grid(&to_grid(data1)?, "XMAS", 4, 0, (1, 1)));
+    assert!(find_word_in_grid(&to_grid(data1)?, "XMAS", 5, 9, (1, 0)));
+    assert_eq!(count(data1, "XMAS")?, 18);
+    assert_eq!(count(&data2, "XMAS")?, 2344);
+    assert_eq!(count_cross(data1, "MAS")?, 9);
+    assert_eq!(count_cross(&data2, "MAS")?, 1815);
+    Ok(())
 }
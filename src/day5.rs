@@ -1,24 +1,54 @@
 #![allow(dead_code)]
 #![allow(clippy::while_let_on_iterator)]
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use text_io::try_scan;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 struct Rule {
     before: usize,
     after: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct Update {
     pages: Vec<usize>,
 }
 
+/// Successor sets keyed by page, so checking a pair of pages does not scan every rule.
+#[derive(Debug, Default)]
+struct RuleIndex {
+    after: HashMap<usize, HashSet<usize>>,
+}
+
 #[derive(Debug)]
 struct Instructions {
     rules: Vec<Rule>,
     updates: Vec<Update>,
+    index: RuleIndex,
+}
+
+impl RuleIndex {
+    fn new(rules: &[Rule]) -> RuleIndex {
+        let mut index = RuleIndex::default();
+        for rule in rules {
+            index
+                .after
+                .entry(rule.before)
+                .or_default()
+                .insert(rule.after);
+        }
+        index
+    }
+
+    fn must_precede(&self, before: usize, after: usize) -> bool {
+        self.after
+            .get(&before)
+            .is_some_and(|pages| pages.contains(&after))
+    }
 }
 
 fn read_instructions(input: &str) -> Result<Instructions> {
@@ -38,69 +68,504 @@ fn read_instructions(input: &str) -> Result<Instructions> {
         })
         .collect();
 
-    Ok(Instructions { rules, updates })
+    let index = RuleIndex::new(&rules);
+    Ok(Instructions {
+        rules,
+        updates,
+        index,
+    })
+}
+
+fn is_correctly_ordered(update: &Update, index: &RuleIndex) -> bool {
+    update
+        .pages
+        .iter()
+        .tuple_combinations()
+        .all(|(&first, &second)| !index.must_precede(second, first))
 }
 
-fn is_correctly_ordered(update: &Update, rules: &[Rule]) -> bool {
-    rules.iter().all(|rule| {
-        let index_before = update.pages.iter().position(|&p| p == rule.before);
-        let index_after = update.pages.iter().position(|&p| p == rule.after);
-        match (index_before, index_after) {
-            (Some(before), Some(after)) => before < after,
-            _ => true,
+/// The pages of an update in an order satisfying the rules, and whether no other order would.
+#[derive(Debug, PartialEq)]
+struct SortedPages {
+    pages: Vec<usize>,
+    unique: bool,
+}
+
+fn sort_pages(pages: &[usize], index: &RuleIndex) -> Result<SortedPages> {
+    sort_pages_by(pages, |before, after| {
+        index.must_precede(pages[before], pages[after])
+    })
+}
+
+/// Kahn's algorithm over `precedes`, which is given indices into `pages`. Ties are broken by the
+/// original position, so pages that are already in a valid place are left alone.
+fn sort_pages_by(pages: &[usize], precedes: impl Fn(usize, usize) -> bool) -> Result<SortedPages> {
+    if !pages.iter().all_unique() {
+        bail!(
+            "update {} contains a page more than once",
+            pages.iter().join(",")
+        );
+    }
+    let mut successors = vec![Vec::new(); pages.len()];
+    let mut predecessors = vec![Vec::new(); pages.len()];
+    let mut in_degree = vec![0_usize; pages.len()];
+    for (before, after) in (0..pages.len()).tuple_combinations() {
+        for (before, after) in [(before, after), (after, before)] {
+            if precedes(before, after) {
+                successors[before].push(after);
+                predecessors[after].push(before);
+                in_degree[after] += 1;
+            }
         }
+    }
+    let mut ready: BTreeSet<usize> = (0..pages.len()).filter(|&i| in_degree[i] == 0).collect();
+    let mut sorted = Vec::with_capacity(pages.len());
+    let mut unique = true;
+    while let Some(i) = ready.pop_first() {
+        unique &= ready.is_empty();
+        sorted.push(pages[i]);
+        for &j in &successors[i] {
+            in_degree[j] -= 1;
+            if in_degree[j] == 0 {
+                ready.insert(j);
+            }
+        }
+    }
+    if sorted.len() < pages.len() {
+        let cycle = find_cycle(&predecessors, &in_degree);
+        bail!(
+            "rules contain a cycle: {}",
+            cycle.iter().map(|&i| pages[i]).join(" -> ")
+        );
+    }
+    Ok(SortedPages {
+        pages: sorted,
+        unique,
     })
 }
 
-fn fix_update(mut update: Update, rules: &Vec<Rule>) -> Update {
-    let mut changed = true;
-    while changed {
-        changed = false;
-        for rule in rules {
-            if let (Some(index_before), Some(index_after)) = (
-                update.pages.iter().position(|&p| p == rule.before),
-                update.pages.iter().position(|&p| p == rule.after),
-            ) {
-                if index_before > index_after {
-                    if index_before == update.pages.len() - 1 {
-                        update.pages.remove(index_after);
-                        update.pages.push(rule.after);
-                    } else {
-                        update.pages.remove(index_after);
-                        update.pages.insert(index_before + 1, rule.after);
+/// Every node Kahn's algorithm could not remove still has an unremoved predecessor, so walking
+/// predecessors from any of them must eventually revisit a node. Returns the cycle in rule order
+/// with the first node repeated at the end.
+fn find_cycle(predecessors: &[Vec<usize>], in_degree: &[usize]) -> Vec<usize> {
+    let remaining = |i: usize| in_degree[i] > 0;
+    let mut node = (0..in_degree.len()).find(|&i| remaining(i)).unwrap();
+    let mut walk = Vec::new();
+    while !walk.contains(&node) {
+        walk.push(node);
+        node = *predecessors[node].iter().find(|&&p| remaining(p)).unwrap();
+    }
+    let start = walk.iter().position(|&n| n == node).unwrap();
+    let mut cycle = walk.split_off(start);
+    cycle.reverse();
+    cycle.push(cycle[0]);
+    cycle
+}
+
+fn fix_update(update: Update, index: &RuleIndex) -> Result<Update> {
+    Ok(Update {
+        pages: sort_pages(&update.pages, index)?.pages,
+    })
+}
+
+fn has_unique_order(update: &Update, index: &RuleIndex) -> Result<bool> {
+    Ok(sort_pages(&update.pages, index)?.unique)
+}
+
+/// `closure[i][j]` is true when the rules force `pages[i]` somewhere before `pages[j]`, either
+/// directly or through other pages of the same update.
+fn precedence_closure(pages: &[usize], index: &RuleIndex) -> Vec<Vec<bool>> {
+    let mut closure: Vec<Vec<bool>> = pages
+        .iter()
+        .map(|&before| {
+            pages
+                .iter()
+                .map(|&after| index.must_precede(before, after))
+                .collect()
+        })
+        .collect();
+    for k in 0..pages.len() {
+        for i in 0..pages.len() {
+            for j in 0..pages.len() {
+                closure[i][j] |= closure[i][k] && closure[k][j];
+            }
+        }
+    }
+    closure
+}
+
+#[derive(Debug, PartialEq)]
+struct Violation {
+    rule: Rule,
+    before_index: usize,
+    after_index: usize,
+}
+
+/// Takes `page` out of position `from` and reinserts it so that it ends up at position `to`.
+#[derive(Debug, PartialEq)]
+struct Move {
+    page: usize,
+    from: usize,
+    to: usize,
+}
+
+#[derive(Debug)]
+struct ViolationReport {
+    update: Update,
+    violations: Vec<Violation>,
+    moves: Vec<Move>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}@{}|{}@{}",
+            self.rule.before, self.before_index, self.rule.after, self.after_index
+        )
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}->{}", self.page, self.from, self.to)
+    }
+}
+
+impl ViolationReport {
+    fn min_moves(&self) -> usize {
+        self.moves.len()
+    }
+
+    fn apply_moves(&self) -> Update {
+        let mut pages = self.update.pages.clone();
+        for m in &self.moves {
+            pages.remove(m.from);
+            pages.insert(m.to, m.page);
+        }
+        Update { pages }
+    }
+}
+
+/// Pages that can stay where they are form the largest set without a pair in the wrong order.
+/// Pairs in the wrong order make up a partial order, so that set is its largest antichain, which
+/// is read off a maximum matching through König's theorem.
+fn pages_to_keep(closure: &[Vec<bool>]) -> Vec<bool> {
+    let n = closure.len();
+    let inverted = |i: usize, j: usize| i < j && closure[j][i];
+    fn augment(
+        i: usize,
+        n: usize,
+        inverted: &dyn Fn(usize, usize) -> bool,
+        seen: &mut [bool],
+        matched_left: &mut [Option<usize>],
+    ) -> bool {
+        for j in 0..n {
+            if inverted(i, j) && !seen[j] {
+                seen[j] = true;
+                if matched_left[j].is_none_or(|k| augment(k, n, inverted, seen, matched_left)) {
+                    matched_left[j] = Some(i);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    let mut matched_left = vec![None; n];
+    let matched: Vec<bool> = (0..n)
+        .map(|i| augment(i, n, &inverted, &mut vec![false; n], &mut matched_left))
+        .collect();
+    // alternating paths from unmatched left vertices
+    let mut left_reached = vec![false; n];
+    let mut right_reached = vec![false; n];
+    let mut queue: Vec<usize> = (0..n).filter(|&i| !matched[i]).collect();
+    for &i in &queue {
+        left_reached[i] = true;
+    }
+    while let Some(i) = queue.pop() {
+        for j in 0..n {
+            if inverted(i, j) && !right_reached[j] {
+                right_reached[j] = true;
+                if let Some(k) = matched_left[j] {
+                    if !left_reached[k] {
+                        left_reached[k] = true;
+                        queue.push(k);
                     }
-                    changed = true;
                 }
-            };
+            }
+        }
+    }
+    (0..n)
+        .map(|i| left_reached[i] && !right_reached[i])
+        .collect()
+}
+
+fn violation_report(update: &Update, index: &RuleIndex) -> Result<ViolationReport> {
+    let pages = &update.pages;
+    let violations = (0..pages.len())
+        .tuple_combinations()
+        .filter(|&(i, j)| index.must_precede(pages[j], pages[i]))
+        .map(|(i, j)| Violation {
+            rule: Rule {
+                before: pages[j],
+                after: pages[i],
+            },
+            before_index: j,
+            after_index: i,
+        })
+        .collect();
+    let closure = precedence_closure(pages, index);
+    let keep = pages_to_keep(&closure);
+    let target = sort_pages_by(pages, |before, after| {
+        closure[before][after] || (keep[before] && keep[after] && before < after)
+    })?
+    .pages;
+    let mut current = pages.clone();
+    let mut moves = Vec::new();
+    for (to, &page) in target.iter().enumerate() {
+        if keep[pages.iter().position(|&p| p == page).unwrap()] {
+            continue;
+        }
+        let from = current.iter().position(|&p| p == page).unwrap();
+        current.remove(from);
+        let to = match to {
+            0 => 0,
+            _ => current.iter().position(|&p| p == target[to - 1]).unwrap() + 1,
+        };
+        current.insert(to, page);
+        moves.push(Move { page, from, to });
+    }
+    Ok(ViolationReport {
+        update: update.clone(),
+        violations,
+        moves,
+    })
+}
+
+fn violation_table(instructions: &Instructions) -> Result<String> {
+    let mut rows = vec![[
+        "update".to_string(),
+        "pages".to_string(),
+        "violated rules".to_string(),
+        "min moves".to_string(),
+        "moves".to_string(),
+    ]];
+    for (number, update) in instructions.updates.iter().enumerate() {
+        if is_correctly_ordered(update, &instructions.index) {
+            continue;
+        }
+        let report = violation_report(update, &instructions.index)?;
+        rows.push([
+            number.to_string(),
+            update.pages.iter().join(","),
+            report.violations.iter().join(" "),
+            report.min_moves().to_string(),
+            report.moves.iter().join(" "),
+        ]);
+    }
+    let widths: Vec<usize> = (0..5)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+        .collect();
+    Ok(rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:width$}", cell))
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .join("\n"))
+}
+
+/// `masks[i]` has bit `j` set when a rule puts `pages[j]` before `pages[i]`.
+fn predecessor_masks(pages: &[usize], index: &RuleIndex) -> Result<Vec<u64>> {
+    if pages.len() > 64 {
+        bail!("updates with more than 64 pages are not supported");
+    }
+    sort_pages(pages, index)?;
+    Ok(pages
+        .iter()
+        .map(|&after| {
+            pages
+                .iter()
+                .enumerate()
+                .filter(|&(_, &before)| index.must_precede(before, after))
+                .fold(0, |mask, (j, _)| mask | (1 << j))
+        })
+        .collect())
+}
+
+/// Counts the orders of an update that satisfy the rules (the linear extensions of the partial
+/// order the rules induce on its pages). Memoized over the sets of pages that can form a prefix.
+fn count_valid_orders(update: &Update, index: &RuleIndex) -> Result<u128> {
+    fn count(placed: u64, predecessors: &[u64], memo: &mut HashMap<u64, u128>) -> u128 {
+        if placed.count_ones() as usize == predecessors.len() {
+            return 1;
+        }
+        if let Some(&n) = memo.get(&placed) {
+            return n;
+        }
+        let n = (0..predecessors.len())
+            .filter(|&i| placed & (1 << i) == 0 && predecessors[i] & !placed == 0)
+            .map(|i| count(placed | (1 << i), predecessors, memo))
+            .sum();
+        memo.insert(placed, n);
+        n
+    }
+    let predecessors = predecessor_masks(&update.pages, index)?;
+    Ok(count(0, &predecessors, &mut HashMap::new()))
+}
+
+/// Lazily yields every order of an update's pages that satisfies the rules.
+struct ValidOrders {
+    pages: Vec<usize>,
+    predecessors: Vec<u64>,
+    order: Vec<usize>,
+    next_candidate: Vec<usize>,
+    placed: u64,
+    done: bool,
+}
+
+impl ValidOrders {
+    fn backtrack(&mut self) {
+        self.next_candidate.pop();
+        match self.order.pop() {
+            Some(i) => self.placed &= !(1 << i),
+            None => self.done = true,
         }
     }
-    update
+}
+
+impl Iterator for ValidOrders {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        while !self.done {
+            if self.order.len() == self.pages.len() {
+                let found = self.order.iter().map(|&i| self.pages[i]).collect();
+                self.backtrack();
+                return Some(found);
+            }
+            let depth = self.order.len();
+            let candidate = (self.next_candidate[depth]..self.pages.len())
+                .find(|&i| self.placed & (1 << i) == 0 && self.predecessors[i] & !self.placed == 0);
+            match candidate {
+                Some(i) => {
+                    self.next_candidate[depth] = i + 1;
+                    self.order.push(i);
+                    self.placed |= 1 << i;
+                    self.next_candidate.push(0);
+                }
+                None => self.backtrack(),
+            }
+        }
+        None
+    }
+}
+
+fn valid_orders(update: &Update, index: &RuleIndex) -> Result<ValidOrders> {
+    Ok(ValidOrders {
+        pages: update.pages.clone(),
+        predecessors: predecessor_masks(&update.pages, index)?,
+        order: Vec::new(),
+        next_candidate: vec![0],
+        placed: 0,
+        done: false,
+    })
+}
+
+/// A page can end up anywhere between the number of pages forced before it and the number forced
+/// after it, so this finds every page some valid order puts in the middle without enumerating.
+fn possible_middle_pages(update: &Update, index: &RuleIndex) -> Result<Vec<usize>> {
+    let pages = &update.pages;
+    sort_pages(pages, index)?;
+    let closure = precedence_closure(pages, index);
+    let middle = pages.len() / 2;
+    Ok((0..pages.len())
+        .filter(|&i| {
+            let before = (0..pages.len()).filter(|&j| closure[j][i]).count();
+            let after = (0..pages.len()).filter(|&j| closure[i][j]).count();
+            before <= middle && pages.len() - 1 - after >= middle
+        })
+        .map(|i| pages[i])
+        .collect())
+}
+
+/// Renders the rules as a Graphviz digraph. With an update, only its pages are drawn and rules it
+/// breaks are coloured red. `reduce` drops every rule already implied by a longer chain of rules,
+/// which needs the drawn rules to be free of cycles; broken rules are always kept.
+fn rules_to_dot(
+    instructions: &Instructions,
+    update: Option<&Update>,
+    reduce: bool,
+) -> Result<String> {
+    let index = &instructions.index;
+    let pages: Vec<usize> = match update {
+        Some(update) => update.pages.clone(),
+        None => instructions
+            .rules
+            .iter()
+            .flat_map(|rule| [rule.before, rule.after])
+            .sorted()
+            .dedup()
+            .collect(),
+    };
+    let position = |page: usize| update.and_then(|u| u.pages.iter().position(|&p| p == page));
+    let closure = if reduce {
+        sort_pages(&pages, index)?;
+        precedence_closure(&pages, index)
+    } else {
+        Vec::new()
+    };
+    let mut dot = String::from("digraph rules {\n");
+    for page in &pages {
+        dot += &format!("    {};\n", page);
+    }
+    for &before in &pages {
+        for (v, &after) in pages.iter().enumerate() {
+            if !index.must_precede(before, after) {
+                continue;
+            }
+            let violated =
+                matches!((position(before), position(after)), (Some(b), Some(a)) if b > a);
+            let redundant = reduce
+                && (0..pages.len())
+                    .any(|w| w != v && index.must_precede(before, pages[w]) && closure[w][v]);
+            if violated {
+                dot += &format!("    {} -> {} [color=red];\n", before, after);
+            } else if !redundant {
+                dot += &format!("    {} -> {};\n", before, after);
+            }
+        }
+    }
+    dot += "}\n";
+    Ok(dot)
 }
 
 fn sum_correctly_ordered(instructions: &Instructions) -> usize {
     instructions
         .updates
         .iter()
-        .filter(|u| is_correctly_ordered(u, &instructions.rules))
+        .filter(|u| is_correctly_ordered(u, &instructions.index))
         .map(|u| u.pages[u.pages.len() / 2])
         .sum()
 }
 
 fn sum_incorrectly_ordered(instructions: &Instructions) -> Result<usize> {
-    Ok(instructions
+    instructions
         .updates
         .iter()
-        .filter(|u| !is_correctly_ordered(u, &instructions.rules))
+        .filter(|u| !is_correctly_ordered(u, &instructions.index))
         .cloned()
-        .map(|u| fix_update(u, &instructions.rules))
-        .map(|u| u.pages[u.pages.len() / 2])
-        .sum())
+        .map(|u| fix_update(u, &instructions.index))
+        .map_ok(|u| u.pages[u.pages.len() / 2])
+        .sum()
 }
 
-#[test]
-fn day() -> Result<()> {
-    let data1 = r#"
-47|53
+#[cfg(test)]
+const EXAMPLE: &str = r#"47|53
 97|13
 97|61
 97|47
@@ -128,8 +593,179 @@ fn day() -> Result<()> {
 75,97,47,61,53
 61,13,29
 97,13,75,29,47
+"#;
+
+#[test]
+fn test_fix_update() -> Result<()> {
+    let instructions = read_instructions("1|2\n2|3\n1|4\n\n3,2,1\n4,2\n1,4,2\n4,3,2")?;
+    let updates = &instructions.updates;
+    let index = &instructions.index;
+    assert!(index.must_precede(1, 2));
+    assert!(!index.must_precede(2, 1));
+    assert!(!index.must_precede(1, 3));
+    assert_eq!(fix_update(updates[0].clone(), index)?.pages, vec![1, 2, 3]);
+    assert!(has_unique_order(&updates[0], index)?);
+    // 4 and 2 are unrelated, so any order is valid and the original one is kept
+    assert_eq!(fix_update(updates[1].clone(), index)?.pages, vec![4, 2]);
+    assert!(!has_unique_order(&updates[1], index)?);
+    assert_eq!(fix_update(updates[2].clone(), index)?.pages, vec![1, 4, 2]);
+    assert!(!has_unique_order(&updates[2], index)?);
+    assert_eq!(fix_update(updates[3].clone(), index)?.pages, vec![4, 2, 3]);
+
+    let instructions = read_instructions("1|2\n2|3\n3|1\n3|4\n\n4,3,2,1\n4,2,1")?;
+    let err = fix_update(instructions.updates[0].clone(), &instructions.index).unwrap_err();
+    assert_eq!(err.to_string(), "rules contain a cycle: 1 -> 2 -> 3 -> 1");
+    assert!(sum_incorrectly_ordered(&instructions).is_err());
+    // the cycle is broken when page 3 is not part of the update
+    let fixed = fix_update(instructions.updates[1].clone(), &instructions.index)?;
+    assert_eq!(fixed.pages, vec![4, 1, 2]);
+
+    let instructions = read_instructions("1|2\n\n1,2,1")?;
+    assert!(fix_update(instructions.updates[0].clone(), &instructions.index).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_violation_report() -> Result<()> {
+    let instructions = read_instructions("1|2\n2|3\n4|5\n\n3,2,1\n3,1\n2,1,5,4\n5,3,1,4,2")?;
+    let index = &instructions.index;
+    let moves = |update: &Update| -> Result<usize> {
+        let report = violation_report(update, index)?;
+        assert!(is_correctly_ordered(&report.apply_moves(), index));
+        Ok(report.min_moves())
+    };
+    // 3 and 1 have no rule of their own, but 2 forces 1 before 3
+    assert_eq!(moves(&instructions.updates[0])?, 2);
+    assert_eq!(
+        violation_report(&instructions.updates[0], index)?
+            .violations
+            .len(),
+        2
+    );
+    assert_eq!(moves(&instructions.updates[1])?, 0);
+    assert_eq!(moves(&instructions.updates[2])?, 2);
+    // 5, 1 and 2 can stay where they are, only 3 and 4 move
+    assert_eq!(moves(&instructions.updates[3])?, 2);
+
+    let instructions1 = read_instructions(EXAMPLE)?;
+    let report = violation_report(&instructions1.updates[3], &instructions1.index)?;
+    assert_eq!(
+        report.violations,
+        vec![Violation {
+            rule: Rule {
+                before: 97,
+                after: 75
+            },
+            before_index: 1,
+            after_index: 0
+        }]
+    );
+    assert_eq!(report.min_moves(), 1);
+    assert_eq!(report.apply_moves().pages, vec![97, 75, 47, 61, 53]);
+    let report = violation_report(&instructions1.updates[5], &instructions1.index)?;
+    assert_eq!(report.violations.len(), 4);
+    assert_eq!(report.min_moves(), 2);
+    assert_eq!(report.apply_moves().pages, vec![97, 75, 47, 29, 13]);
+    assert_eq!(
+        violation_table(&instructions1)?,
+        r#"
+update  pages           violated rules                           min moves  moves
+3       75,97,47,61,53  97@1|75@0                                1          75:0->1
+4       61,13,29        29@2|13@1                                1          13:1->2
+5       97,13,75,29,47  75@2|13@1 29@3|13@1 47@4|13@1 47@4|29@3  2          29:3->4 13:1->4
 "#
-    .trim();
+        .trim()
+    );
+
+    let data2 = std::fs::read_to_string("input/day5").unwrap();
+    let instructions2 = read_instructions(&data2)?;
+    for update in &instructions2.updates {
+        let report = violation_report(update, &instructions2.index)?;
+        assert!(is_correctly_ordered(
+            &report.apply_moves(),
+            &instructions2.index
+        ));
+        assert_eq!(report.violations.is_empty(), report.moves.is_empty());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_valid_orders() -> Result<()> {
+    let instructions = read_instructions("1|2\n1|3\n2|4\n\n1,2,3\n3,1,2,4\n5,6,7,8\n3,2,1")?;
+    let index = &instructions.index;
+    let updates = &instructions.updates;
+    assert_eq!(count_valid_orders(&updates[0], index)?, 2);
+    assert_eq!(
+        valid_orders(&updates[0], index)?.collect::<Vec<_>>(),
+        vec![vec![1, 2, 3], vec![1, 3, 2]]
+    );
+    assert_eq!(possible_middle_pages(&updates[0], index)?, vec![2, 3]);
+    assert_eq!(count_valid_orders(&updates[1], index)?, 3);
+    assert_eq!(possible_middle_pages(&updates[1], index)?, vec![3, 2, 4]);
+    assert_eq!(count_valid_orders(&updates[2], index)?, 24);
+    let orders: Vec<_> = valid_orders(&updates[2], index)?.collect();
+    assert_eq!(orders.len(), 24);
+    assert!(orders.iter().all_unique());
+    assert_eq!(valid_orders(&updates[2], index)?.take(2).count(), 2);
+    for order in valid_orders(&updates[1], index)? {
+        assert!(is_correctly_ordered(&Update { pages: order }, index));
+    }
+    assert_eq!(count_valid_orders(&updates[3], index)?, 2);
+    assert_eq!(count_valid_orders(&Update { pages: vec![] }, index)?, 1);
+    assert_eq!(valid_orders(&Update { pages: vec![] }, index)?.count(), 1);
+
+    let pages = (10..26).collect();
+    assert_eq!(
+        count_valid_orders(&Update { pages }, index)?,
+        (1..=16_u128).product()
+    );
+
+    // every update of the puzzle input has exactly one valid order, so its middle page is clear
+    let data2 = std::fs::read_to_string("input/day5").unwrap();
+    let instructions2 = read_instructions(&data2)?;
+    for update in &instructions2.updates {
+        assert_eq!(count_valid_orders(update, &instructions2.index)?, 1);
+        assert_eq!(
+            possible_middle_pages(update, &instructions2.index)?.len(),
+            1
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_rules_to_dot() -> Result<()> {
+    let instructions1 = read_instructions(EXAMPLE)?;
+    let data2 = std::fs::read_to_string("input/day5").unwrap();
+    let instructions2 = read_instructions(&data2)?;
+    assert_eq!(
+        rules_to_dot(&instructions1, Some(&instructions1.updates[3]), true)?,
+        r#"digraph rules {
+    75;
+    97;
+    47;
+    61;
+    53;
+    75 -> 47;
+    97 -> 75 [color=red];
+    47 -> 61;
+    61 -> 53;
+}
+"#
+    );
+    let dot = rules_to_dot(&instructions1, None, false)?;
+    assert_eq!(dot.matches(" -> ").count(), 21);
+    let dot = rules_to_dot(&instructions1, None, true)?;
+    assert_eq!(dot.matches(" -> ").count(), 6);
+    assert!(rules_to_dot(&instructions2, None, true).is_err());
+    assert!(rules_to_dot(&instructions2, None, false).is_ok());
+    Ok(())
+}
+
+#[test]
+fn day() -> Result<()> {
+    let data1 = EXAMPLE;
     let instructions1 = read_instructions(data1)?;
     assert_eq!(instructions1.rules.len(), 21);
     assert_eq!(instructions1.updates.len(), 6);
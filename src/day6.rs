@@ -1,8 +1,10 @@
 use anyhow::Result;
+use itertools::Itertools;
 use rayon::prelude::*;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Direction {
     Up,
     Right,
@@ -10,21 +12,104 @@ pub enum Direction {
     Left,
 }
 
+/// What the guard does when it bumps into an obstacle.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TurnPolicy {
+    Right,
+    Left,
+    // right, left, right, ...
+    Alternate,
+    Reverse,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tile {
+    Floor,
+    Obstacle,
+    // only enterable walking in this direction, an obstacle otherwise
+    OneWay(Direction),
+    // walking onto it moves the guard to the other portal with the same label
+    Portal(char),
+}
+
 #[derive(Clone)]
 pub struct Game {
-    map: Vec<Vec<char>>,
+    map: Vec<Vec<Tile>>,
+    portals: HashMap<(isize, isize), (isize, isize)>,
     x: isize,
     y: isize,
     direction: Direction,
+    policy: TurnPolicy,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GuardState {
+    pub x: isize,
+    pub y: isize,
+    pub direction: Direction,
+    pub turned: bool,
+}
+
+/// Yields the starting state and then the state after every single step or turn, for as long as
+/// the guard is on the map. A looping patrol never ends, so callers have to stop on their own.
+pub struct GuardSteps {
+    game: Game,
+    extra_obstacle: Option<(isize, isize)>,
+    started: bool,
+    turns: usize,
+}
+
+/// `path` holds every state from the start, as yielded by `guard_steps`, so its length is
+/// `steps + turns + 1`.
 #[derive(PartialEq, Debug)]
 pub enum SimulationResult {
-    Done(usize),
-    Loop,
+    Done {
+        distinct: usize,
+        path: Vec<GuardState>,
+        steps: usize,
+        turns: usize,
+        // last cell on the map and the direction the guard leaves it in
+        exit: ((isize, isize), Direction),
+    },
+    Loop {
+        path: Vec<GuardState>,
+        steps: usize,
+        turns: usize,
+        // index into `path` of the first state whose position and direction repeat
+        entry_step: usize,
+        cycle: Vec<GuardState>,
+    },
+}
+
+impl SimulationResult {
+    pub fn is_loop(&self) -> bool {
+        matches!(self, SimulationResult::Loop { .. })
+    }
+    pub fn path(&self) -> &[GuardState] {
+        match self {
+            SimulationResult::Done { path, .. } | SimulationResult::Loop { path, .. } => path,
+        }
+    }
 }
 
 impl Direction {
+    pub fn from_marker(c: char) -> Option<Direction> {
+        match c {
+            '^' => Some(Direction::Up),
+            '>' => Some(Direction::Right),
+            'v' => Some(Direction::Down),
+            '<' => Some(Direction::Left),
+            _ => None,
+        }
+    }
+    pub fn to_marker(&self) -> char {
+        match self {
+            Direction::Up => '^',
+            Direction::Right => '>',
+            Direction::Down => 'v',
+            Direction::Left => '<',
+        }
+    }
     pub fn turn_right(&self) -> Direction {
         match self {
             Direction::Up => Direction::Right,
@@ -33,6 +118,15 @@ impl Direction {
             Direction::Left => Direction::Up,
         }
     }
+    pub fn turn_left(&self) -> Direction {
+        self.reverse().turn_right()
+    }
+    pub fn reverse(&self) -> Direction {
+        self.turn_right().turn_right()
+    }
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
     pub fn to_movement(&self) -> (isize, isize) {
         match self {
             Direction::Up => (0, -1),
@@ -43,7 +137,61 @@ impl Direction {
     }
 }
 
+impl Tile {
+    pub fn from_char(c: char) -> Option<Tile> {
+        match c {
+            '.' => Some(Tile::Floor),
+            '#' => Some(Tile::Obstacle),
+            '↑' => Some(Tile::OneWay(Direction::Up)),
+            '→' => Some(Tile::OneWay(Direction::Right)),
+            '↓' => Some(Tile::OneWay(Direction::Down)),
+            '←' => Some(Tile::OneWay(Direction::Left)),
+            '0'..='9' => Some(Tile::Portal(c)),
+            _ => None,
+        }
+    }
+    pub fn to_char(&self) -> char {
+        match self {
+            Tile::Floor => '.',
+            Tile::Obstacle => '#',
+            Tile::OneWay(Direction::Up) => '↑',
+            Tile::OneWay(Direction::Right) => '→',
+            Tile::OneWay(Direction::Down) => '↓',
+            Tile::OneWay(Direction::Left) => '←',
+            Tile::Portal(label) => *label,
+        }
+    }
+}
+
+impl TurnPolicy {
+    /// `turns` is how often the guard has turned before this bump.
+    pub fn turn(&self, direction: Direction, turns: usize) -> Direction {
+        match self {
+            TurnPolicy::Right => direction.turn_right(),
+            TurnPolicy::Left => direction.turn_left(),
+            TurnPolicy::Alternate if turns.is_multiple_of(2) => direction.turn_right(),
+            TurnPolicy::Alternate => direction.turn_left(),
+            TurnPolicy::Reverse => direction.reverse(),
+        }
+    }
+    /// Guards in the same cell and direction only behave the same from there on if their next turn
+    /// is the same too, so loop detection has to include this in the state.
+    pub fn phase(&self, turns: usize) -> usize {
+        turns % self.phases()
+    }
+    pub fn phases(&self) -> usize {
+        match self {
+            TurnPolicy::Alternate => 2,
+            _ => 1,
+        }
+    }
+}
+
 impl Game {
+    pub fn with_policy(mut self, policy: TurnPolicy) -> Game {
+        self.policy = policy;
+        self
+    }
     pub fn width(&self) -> isize {
         self.map[0].len() as isize
     }
@@ -56,73 +204,284 @@ impl Game {
     pub fn within_bounds(&self, x: isize, y: isize) -> bool {
         x >= 0 && y >= 0 && x < self.width() && y < self.height()
     }
-    pub fn at(&self, x: isize, y: isize) -> char {
+    pub fn at(&self, x: isize, y: isize) -> Tile {
         self.map[y as usize][x as usize]
     }
     pub fn is_obstacle(&self, x: isize, y: isize) -> bool {
         if !self.within_bounds(x, y) {
             return false;
         }
-        self.at(x, y) == '#'
+        self.at(x, y) == Tile::Obstacle
+    }
+    /// Whether a guard walking in `direction` has to turn in front of `(x, y)`.
+    pub fn blocks(&self, x: isize, y: isize, direction: Direction) -> bool {
+        if !self.within_bounds(x, y) {
+            return false;
+        }
+        match self.at(x, y) {
+            Tile::Obstacle => true,
+            Tile::OneWay(allowed) => allowed != direction,
+            Tile::Floor | Tile::Portal(_) => false,
+        }
+    }
+    pub fn portal_exit(&self, x: isize, y: isize) -> Option<(isize, isize)> {
+        self.portals.get(&(x, y)).copied()
+    }
+    /// Whether the map only has floor and obstacles.
+    pub fn is_plain(&self) -> bool {
+        self.map
+            .iter()
+            .flatten()
+            .all(|&tile| matches!(tile, Tile::Floor | Tile::Obstacle))
+    }
+    pub fn with_obstacles(&self, obstacles: &[(isize, isize)]) -> Game {
+        let mut game = self.clone();
+        for &(x, y) in obstacles {
+            game.map[y as usize][x as usize] = Tile::Obstacle;
+        }
+        game
+    }
+    pub fn floor_count(&self) -> usize {
+        self.map
+            .iter()
+            .flatten()
+            .filter(|&&tile| tile == Tile::Floor)
+            .count()
     }
 }
 
-pub fn read_game_start(input: &str) -> Result<Game> {
-    let mut pos: Option<(isize, isize)> = None;
+/// Where a guard walking straight ahead ends up.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Jump {
+    Exit,
+    // stops on this cell in front of an obstacle
+    Turn(isize, isize),
+    // walks onto this portal
+    Portal(isize, isize),
+}
+
+/// The `Jump` for every cell and direction.
+pub struct JumpTable {
+    width: isize,
+    jumps: Vec<[Jump; 4]>,
+}
+
+impl JumpTable {
+    pub fn new(game: &Game) -> JumpTable {
+        let mut table = JumpTable {
+            width: game.width(),
+            jumps: vec![[Jump::Exit; 4]; game.size()],
+        };
+        for direction in [
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+            Direction::Left,
+        ] {
+            let (dx, dy) = direction.to_movement();
+            // visit cells so that the next cell in `direction` is always filled in first
+            let xs: Vec<isize> = match dx {
+                1 => (0..game.width()).rev().collect(),
+                _ => (0..game.width()).collect(),
+            };
+            let ys: Vec<isize> = match dy {
+                1 => (0..game.height()).rev().collect(),
+                _ => (0..game.height()).collect(),
+            };
+            for &y in &ys {
+                for &x in &xs {
+                    let (next_x, next_y) = (x + dx, y + dy);
+                    let jump = if !game.within_bounds(next_x, next_y) {
+                        Jump::Exit
+                    } else if game.blocks(next_x, next_y, direction) {
+                        Jump::Turn(x, y)
+                    } else if game.portal_exit(next_x, next_y).is_some() {
+                        Jump::Portal(next_x, next_y)
+                    } else {
+                        table.jump(next_x, next_y, direction)
+                    };
+                    let index = table.index(x, y);
+                    table.jumps[index][direction.index()] = jump;
+                }
+            }
+        }
+        table
+    }
+    fn index(&self, x: isize, y: isize) -> usize {
+        (y * self.width + x) as usize
+    }
+    pub fn jump(&self, x: isize, y: isize, direction: Direction) -> Jump {
+        self.jumps[self.index(x, y)][direction.index()]
+    }
+}
+
+/// One game per guard marker (`^`, `>`, `v` or `<`) in reading order. Guards patrol the same map
+/// independently and do not block each other.
+pub fn read_guards(input: &str) -> Result<Vec<Game>> {
+    let mut guards = Vec::new();
     let mut map = Vec::new();
+    let mut portals: HashMap<char, Vec<(isize, isize)>> = HashMap::new();
     for (y, line) in input.lines().enumerate() {
         let mut row = Vec::new();
         for (x, c) in line.chars().enumerate() {
-            if c == '^' {
-                pos = Some((x as isize, y as isize));
-                row.push('.');
-            } else {
-                row.push(c);
+            let (x, y) = (x as isize, y as isize);
+            if let Some(direction) = Direction::from_marker(c) {
+                guards.push((x, y, direction));
+                row.push(Tile::Floor);
+                continue;
             }
+            let Some(tile) = Tile::from_char(c) else {
+                anyhow::bail!("unknown tile {:?} at ({}, {})", c, x, y);
+            };
+            if let Tile::Portal(label) = tile {
+                portals.entry(label).or_default().push((x, y));
+            }
+            row.push(tile);
+        }
+        if let Some(first) = map.first().map(Vec::len).filter(|&w| w != row.len()) {
+            anyhow::bail!("row {} has width {}, expected {}", y, row.len(), first);
         }
         map.push(row);
     }
-    let Some((x, y)) = pos else {
-        anyhow::bail!("failed to find starting position");
-    };
-    Ok(Game {
-        map,
-        x,
-        y,
-        direction: Direction::Up,
-    })
+    let mut exits = HashMap::new();
+    for (label, ends) in portals.into_iter().sorted() {
+        let &[a, b] = ends.as_slice() else {
+            anyhow::bail!(
+                "portal {:?} appears {} times, expected 2",
+                label,
+                ends.len()
+            );
+        };
+        exits.insert(a, b);
+        exits.insert(b, a);
+    }
+    Ok(guards
+        .into_iter()
+        .map(|(x, y, direction)| Game {
+            map: map.clone(),
+            portals: exits.clone(),
+            x,
+            y,
+            direction,
+            policy: TurnPolicy::Right,
+        })
+        .collect())
 }
 
-pub fn run_simulation(mut game: Game, extra_obstacle: Option<(isize, isize)>) -> SimulationResult {
-    let mut visited_incl_dir = HashSet::with_capacity(game.size());
-    let mut visited_positions = Vec::with_capacity(game.size());
-    loop {
+pub fn read_game_start(input: &str) -> Result<Game> {
+    let mut guards = read_guards(input)?;
+    match guards.len() {
+        0 => anyhow::bail!("failed to find starting position"),
+        1 => Ok(guards.remove(0)),
+        n => anyhow::bail!("found {} guards, use read_guards for maps with several", n),
+    }
+}
+
+impl Iterator for GuardSteps {
+    type Item = GuardState;
+
+    fn next(&mut self) -> Option<GuardState> {
+        let game = &mut self.game;
         if !game.within_bounds(game.x, game.y) {
-            visited_positions.sort();
-            visited_positions.dedup();
-            return SimulationResult::Done(visited_positions.len());
-        }
-        let movement = game.direction.to_movement();
-        let obstacle_x = game.x + movement.0;
-        let obstacle_y = game.y + movement.1;
-        if game.is_obstacle(obstacle_x, obstacle_y)
-            || extra_obstacle == Some((obstacle_x, obstacle_y))
-        {
-            game.direction = game.direction.turn_right();
-        } else {
-            let new_pos = (game.x, game.y, movement.0, movement.1);
-            if visited_incl_dir.contains(&new_pos) {
-                return SimulationResult::Loop;
-            }
-            visited_incl_dir.insert(new_pos);
-            visited_positions.push((game.x, game.y));
+            return None;
+        }
+        let mut turned = false;
+        if self.started {
             let movement = game.direction.to_movement();
-            game.x += movement.0;
-            game.y += movement.1;
+            let obstacle_x = game.x + movement.0;
+            let obstacle_y = game.y + movement.1;
+            if game.blocks(obstacle_x, obstacle_y, game.direction)
+                || self.extra_obstacle == Some((obstacle_x, obstacle_y))
+            {
+                game.direction = game.policy.turn(game.direction, self.turns);
+                self.turns += 1;
+                turned = true;
+            } else {
+                game.x += movement.0;
+                game.y += movement.1;
+                if !game.within_bounds(game.x, game.y) {
+                    return None;
+                }
+                if let Some((exit_x, exit_y)) = game.portal_exit(game.x, game.y) {
+                    (game.x, game.y) = (exit_x, exit_y);
+                }
+            }
         }
+        self.started = true;
+        Some(GuardState {
+            x: game.x,
+            y: game.y,
+            direction: game.direction,
+            turned,
+        })
     }
 }
 
+pub fn guard_steps(game: Game, extra_obstacle: Option<(isize, isize)>) -> GuardSteps {
+    GuardSteps {
+        game,
+        extra_obstacle,
+        started: false,
+        turns: 0,
+    }
+}
+
+pub fn run_simulation(game: Game, extra_obstacle: Option<(isize, isize)>) -> SimulationResult {
+    let mut visited_incl_dir = HashMap::with_capacity(game.size());
+    let mut path: Vec<GuardState> = Vec::with_capacity(game.size());
+    let policy = game.policy;
+    let mut turns = 0;
+    for state in guard_steps(game, extra_obstacle) {
+        turns += state.turned as usize;
+        let key = (
+            state.x,
+            state.y,
+            state.direction.index(),
+            policy.phase(turns),
+        );
+        if let Some(&entry_step) = visited_incl_dir.get(&key) {
+            let turns = path.iter().filter(|s| s.turned).count();
+            return SimulationResult::Loop {
+                steps: path.len() - 1 - turns,
+                turns,
+                entry_step,
+                cycle: path[entry_step..].to_vec(),
+                path,
+            };
+        }
+        visited_incl_dir.insert(key, path.len());
+        path.push(state);
+    }
+    let turns = path.iter().filter(|s| s.turned).count();
+    let last = path.last().unwrap();
+    SimulationResult::Done {
+        distinct: path.iter().map(|s| (s.x, s.y)).unique().count(),
+        steps: path.len() - 1 - turns,
+        turns,
+        exit: ((last.x, last.y), last.direction),
+        path,
+    }
+}
+
+/// Whether the patrol loops, like `run_simulation(..).is_loop()` but without keeping the path,
+/// only a flat bitset of the (cell, direction, turn phase) states seen so far.
+pub fn patrol_loops(game: &Game, extra_obstacle: Option<(isize, isize)>) -> bool {
+    let width = game.width() as usize;
+    let phases = game.policy.phases();
+    let mut visited = vec![0u64; (game.size() * 4 * phases).div_ceil(64)];
+    let mut turns = 0;
+    for state in guard_steps(game.clone(), extra_obstacle) {
+        turns += state.turned as usize;
+        let cell = state.y as usize * width + state.x as usize;
+        let index = (cell * 4 + state.direction.index()) * phases + game.policy.phase(turns);
+        if visited[index / 64] & (1 << (index % 64)) != 0 {
+            return true;
+        }
+        visited[index / 64] |= 1 << (index % 64);
+    }
+    false
+}
+
 pub fn find_loops(game: Game) -> usize {
     (0..game.size())
         .into_par_iter()
@@ -132,53 +491,1109 @@ pub fn find_loops(game: Game) -> usize {
                 (i / game.width() as usize) as isize,
             )
         })
-        .filter(|&(x, y)| !game.is_obstacle(x, y))
+        .filter(|&(x, y)| game.at(x, y) == Tile::Floor)
         .filter(|&(x, y)| !(game.x == x && game.y == y))
-        .filter(|&pos| {
-            let result = run_simulation(game.clone(), Some(pos));
-            matches!(result, SimulationResult::Loop)
-        })
+        .filter(|&pos| patrol_loops(&game, Some(pos)))
         .count()
 }
 
+/// Distinct cells the guard walks over on its original patrol, in the order first visited.
+pub fn patrol_positions(game: &Game) -> Vec<(isize, isize)> {
+    run_simulation(game.clone(), None)
+        .path()
+        .iter()
+        .map(|state| (state.x, state.y))
+        .unique()
+        .collect()
+}
+
+/// Same answer as `run_simulation` returning `Loop`, but the guard jumps from turn to turn (or
+/// portal to portal) using `table`, and visited states live in a flat bitset indexed by cell and
+/// direction.
+pub fn loops_with_jumps(
+    game: &Game,
+    table: &JumpTable,
+    extra_obstacle: Option<(isize, isize)>,
+) -> bool {
+    let phases = game.policy.phases();
+    // turning in a cell and arriving in it through a portal are recorded apart, as a guard that
+    // comes out of a portal can turn in the same cell right after
+    let mut visited = vec![0_u64; (game.size() * 4 * phases * 2).div_ceil(64)];
+    let (mut x, mut y, mut direction) = (game.x, game.y, game.direction);
+    let mut turns = 0;
+    loop {
+        let (dx, dy) = direction.to_movement();
+        let mut jump = table.jump(x, y, direction);
+        if let Some((obstacle_x, obstacle_y)) = extra_obstacle {
+            // distance along the walking direction, only meaningful when on the same line
+            let on_line = if dx != 0 {
+                obstacle_y == y
+            } else {
+                obstacle_x == x
+            };
+            let distance = (obstacle_x - x) * dx + (obstacle_y - y) * dy;
+            let jump_distance = match jump {
+                Jump::Exit => None,
+                Jump::Turn(to_x, to_y) | Jump::Portal(to_x, to_y) => {
+                    Some((to_x - x) * dx + (to_y - y) * dy)
+                }
+            };
+            if on_line && distance > 0 && jump_distance.is_none_or(|d| distance <= d) {
+                jump = Jump::Turn(obstacle_x - dx, obstacle_y - dy);
+            }
+        }
+        let turn = match jump {
+            Jump::Exit => return false,
+            Jump::Turn(to_x, to_y) => {
+                (x, y) = (to_x, to_y);
+                true
+            }
+            Jump::Portal(to_x, to_y) => {
+                (x, y) = game.portal_exit(to_x, to_y).unwrap();
+                false
+            }
+        };
+        let state =
+            ((table.index(x, y) * 4 + direction.index()) * phases + game.policy.phase(turns)) * 2
+                + turn as usize;
+        if visited[state / 64] & (1 << (state % 64)) != 0 {
+            return true;
+        }
+        visited[state / 64] |= 1 << (state % 64);
+        if turn {
+            direction = game.policy.turn(direction, turns);
+            turns += 1;
+        }
+    }
+}
+
+/// `find_loops` restricted to cells on the original patrol, since an obstacle anywhere else is
+/// never reached, and simulated with `loops_with_jumps`. If the original patrol already loops,
+/// every unreached cell keeps it looping and is counted without simulating.
+pub fn find_loops_fast(game: Game) -> usize {
+    let table = JumpTable::new(&game);
+    let positions: Vec<_> = patrol_positions(&game)
+        .into_iter()
+        .filter(|&(x, y)| game.at(x, y) == Tile::Floor)
+        .collect();
+    let unreached = match loops_with_jumps(&game, &table, None) {
+        true => game.floor_count() - positions.len(),
+        false => 0,
+    };
+    unreached
+        + positions
+            .into_par_iter()
+            .filter(|&(x, y)| !(game.x == x && game.y == y))
+            .filter(|&pos| loops_with_jumps(&game, &table, Some(pos)))
+            .count()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Objective {
+    // keep the guard walking as long as possible before it leaves the map
+    LongestPatrol,
+    // trap the guard in the loop with the fewest states
+    SmallestLoop,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Placement {
+    pub obstacles: Vec<(isize, isize)>,
+    pub result: SimulationResult,
+}
+
+impl Objective {
+    /// Lower is better, `None` if the result does not count at all.
+    fn cost(&self, result: &SimulationResult) -> Option<isize> {
+        match (self, result) {
+            (Objective::LongestPatrol, SimulationResult::Done { steps, .. }) => {
+                Some(-(*steps as isize))
+            }
+            (Objective::SmallestLoop, SimulationResult::Loop { cycle, .. }) => {
+                Some(cycle.len() as isize)
+            }
+            _ => None,
+        }
+    }
+    fn wants_loop(&self) -> bool {
+        matches!(self, Objective::SmallestLoop)
+    }
+}
+
+// <cost, number of obstacles, obstacles as (y, x)>, lower is better
+type PlacementKey = (isize, usize, Vec<(isize, isize)>);
+
+/// The best placement for `objective` with up to `budget` extra obstacles, or `None` if no
+/// placement meets it. An obstacle only matters once the guard runs into it, and until it runs
+/// into the first one it walks the patrol it would without them, so trying every cell of the
+/// current patrol for the next obstacle covers every placement. That is exact, but the work grows
+/// with the patrol length to the power of `budget`, so keep the budget small.
+pub fn optimize_obstacles(game: &Game, budget: usize, objective: Objective) -> Option<Placement> {
+    let visited = Mutex::new(HashSet::new());
+    search_obstacles(game, Vec::new(), budget, objective, &visited).map(|(_, placement)| placement)
+}
+
+/// `visited` holds the sorted obstacle sets already scored, since the same set is reached once
+/// for every order its obstacles can be placed in.
+fn search_obstacles(
+    game: &Game,
+    mut obstacles: Vec<(isize, isize)>,
+    budget: usize,
+    objective: Objective,
+    visited: &Mutex<HashSet<Vec<(isize, isize)>>>,
+) -> Option<(PlacementKey, Placement)> {
+    obstacles.sort_by_key(|&(x, y)| (y, x));
+    if !visited.lock().unwrap().insert(obstacles.clone()) {
+        return None;
+    }
+    let blocked = game.with_obstacles(&obstacles);
+    let result = run_simulation(blocked.clone(), None);
+    let candidates: Vec<_> = match budget {
+        0 => Vec::new(),
+        _ => result
+            .path()
+            .iter()
+            .map(|s| (s.x, s.y))
+            .unique()
+            .filter(|&(x, y)| game.at(x, y) == Tile::Floor && (x, y) != (game.x, game.y))
+            .filter(|pos| !obstacles.contains(pos))
+            // the last obstacle has to leave the guard looping or not as the objective needs,
+            // which is cheap to check without the full path
+            .filter(|&pos| {
+                budget > 1 || patrol_loops(&blocked, Some(pos)) == objective.wants_loop()
+            })
+            .collect(),
+    };
+    let current = objective.cost(&result).map(|cost| {
+        let key = (
+            cost,
+            obstacles.len(),
+            obstacles.iter().map(|&(x, y)| (y, x)).collect(),
+        );
+        (
+            key,
+            Placement {
+                obstacles: obstacles.clone(),
+                result,
+            },
+        )
+    });
+    candidates
+        .into_par_iter()
+        .filter_map(|pos| {
+            let mut next = obstacles.clone();
+            next.push(pos);
+            search_obstacles(game, next, budget - 1, objective, visited)
+        })
+        .chain(current)
+        .min_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs))
+}
+
+/// Which way the guard crossed each floor cell of `path`: `(vertical, horizontal)`. Turning
+/// counts as both, so turns come out as `+`.
+fn crossings(game: &Game, path: &[GuardState]) -> HashMap<(isize, isize), (bool, bool)> {
+    let mut crossed: HashMap<(isize, isize), (bool, bool)> = HashMap::new();
+    for (from, to) in path.iter().tuple_windows() {
+        let vertical = matches!(from.direction, Direction::Up | Direction::Down);
+        let cells = match to.turned {
+            true => vec![(to.x, to.y)],
+            // a portal jump leaves the walked cell behind, so mark both ends of the step
+            false => vec![(from.x, from.y), (to.x, to.y)],
+        };
+        for (x, y) in cells {
+            if game.at(x, y) != Tile::Floor {
+                continue;
+            }
+            let entry = crossed.entry((x, y)).or_default();
+            entry.0 |= vertical || to.turned;
+            entry.1 |= !vertical || to.turned;
+        }
+    }
+    crossed
+}
+
+/// Draws the map with the patrol like the puzzle statement: `|` and `-` for walked cells, `+`
+/// where the guard turned or crossed its own path, its starting marker and `O` for the extra
+/// obstacle.
+pub fn render(game: &Game, path: &[GuardState], extra_obstacle: Option<(isize, isize)>) -> String {
+    let crossed = crossings(game, path);
+    (0..game.height())
+        .map(|y| {
+            (0..game.width())
+                .map(|x| {
+                    if (x, y) == (game.x, game.y) {
+                        return game.direction.to_marker();
+                    }
+                    if extra_obstacle == Some((x, y)) {
+                        return 'O';
+                    }
+                    match crossed.get(&(x, y)) {
+                        Some((true, true)) => '+',
+                        Some((true, false)) => '|',
+                        Some((false, true)) => '-',
+                        _ => game.at(x, y).to_char(),
+                    }
+                })
+                .collect::<String>()
+        })
+        .join("\n")
+}
+
+/// A binary PPM image of the map with `path` walked so far, `scale` pixels per cell. The guard is
+/// drawn at the last state of `path`.
+pub fn render_ppm(
+    game: &Game,
+    path: &[GuardState],
+    extra_obstacle: Option<(isize, isize)>,
+    scale: usize,
+) -> Vec<u8> {
+    let crossed = crossings(game, path);
+    let guard = path.last().map(|s| (s.x, s.y));
+    let colour = |x: isize, y: isize| -> [u8; 3] {
+        if guard == Some((x, y)) {
+            return [220, 40, 40];
+        }
+        if extra_obstacle == Some((x, y)) {
+            return [240, 160, 20];
+        }
+        if crossed.contains_key(&(x, y)) {
+            return [60, 140, 230];
+        }
+        match game.at(x, y) {
+            Tile::Floor => [20, 20, 20],
+            Tile::Obstacle => [160, 160, 160],
+            Tile::OneWay(_) => [60, 180, 90],
+            Tile::Portal(_) => [170, 80, 200],
+        }
+    };
+    let (width, height) = (game.width() as usize, game.height() as usize);
+    let mut image = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            image.extend(colour((x / scale) as isize, (y / scale) as isize));
+        }
+    }
+    image
+}
+
+/// Writes `frame_00000.ppm`, `frame_00001.ppm`, ... to `directory`, one per state of the patrol
+/// (up to the point where it starts repeating), and returns the number of frames.
+pub fn write_ppm_frames(
+    game: &Game,
+    extra_obstacle: Option<(isize, isize)>,
+    directory: &std::path::Path,
+    scale: usize,
+) -> Result<usize> {
+    std::fs::create_dir_all(directory)?;
+    let result = run_simulation(game.clone(), extra_obstacle);
+    let path = result.path();
+    for frame in 0..path.len() {
+        let image = render_ppm(game, &path[..=frame], extra_obstacle, scale);
+        std::fs::write(directory.join(format!("frame_{:05}.ppm", frame)), image)?;
+    }
+    Ok(path.len())
+}
+
+// <from, to>, both inclusive
+type Segment = ((i64, i64), (i64, i64));
+
+/// A map kept as sorted obstacle coordinates per row and per column, so memory grows with the
+/// number of obstacles instead of the area and bounds can be millions of cells wide.
+#[derive(Clone)]
+pub struct SparseGame {
+    width: i64,
+    height: i64,
+    x: i64,
+    y: i64,
+    direction: Direction,
+    policy: TurnPolicy,
+    // y -> sorted xs
+    rows: HashMap<i64, Vec<i64>>,
+    // x -> sorted ys
+    columns: HashMap<i64, Vec<i64>>,
+    obstacles: usize,
+}
+
+/// Like `SimulationResult`, but without the per-cell path, which would be as large as the map.
+/// For loops, `steps` and `turns` are counted up to the turn that repeats.
+#[derive(PartialEq, Debug)]
+pub enum SparseResult {
+    Done {
+        distinct: u64,
+        steps: u64,
+        turns: usize,
+        exit: ((i64, i64), Direction),
+    },
+    Loop {
+        steps: u64,
+        turns: usize,
+    },
+}
+
+impl Game {
+    pub fn to_sparse(&self) -> Result<SparseGame> {
+        if !self.is_plain() {
+            anyhow::bail!("sparse maps only support floor and obstacles");
+        }
+        let obstacles = (0..self.height())
+            .cartesian_product(0..self.width())
+            .filter(|&(y, x)| self.is_obstacle(x, y))
+            .map(|(y, x)| (x as i64, y as i64));
+        Ok(SparseGame::new(
+            self.width() as i64,
+            self.height() as i64,
+            (self.x as i64, self.y as i64, self.direction),
+            obstacles,
+        )?
+        .with_policy(self.policy))
+    }
+}
+
+impl SparseGame {
+    pub fn new(
+        width: i64,
+        height: i64,
+        (x, y, direction): (i64, i64, Direction),
+        obstacles: impl IntoIterator<Item = (i64, i64)>,
+    ) -> Result<SparseGame> {
+        let mut game = SparseGame {
+            width,
+            height,
+            x,
+            y,
+            direction,
+            policy: TurnPolicy::Right,
+            rows: HashMap::new(),
+            columns: HashMap::new(),
+            obstacles: 0,
+        };
+        if !game.within_bounds(x, y) {
+            anyhow::bail!(
+                "start ({}, {}) is outside the {}x{} map",
+                x,
+                y,
+                width,
+                height
+            );
+        }
+        for (x, y) in obstacles.into_iter().unique() {
+            if !game.within_bounds(x, y) {
+                anyhow::bail!(
+                    "obstacle ({}, {}) is outside the {}x{} map",
+                    x,
+                    y,
+                    width,
+                    height
+                );
+            }
+            if (x, y) == (game.x, game.y) {
+                anyhow::bail!("start ({}, {}) is on an obstacle", x, y);
+            }
+            game.rows.entry(y).or_default().push(x);
+            game.columns.entry(x).or_default().push(y);
+            game.obstacles += 1;
+        }
+        for line in game.rows.values_mut().chain(game.columns.values_mut()) {
+            line.sort();
+        }
+        Ok(game)
+    }
+    pub fn with_policy(mut self, policy: TurnPolicy) -> SparseGame {
+        self.policy = policy;
+        self
+    }
+    pub fn within_bounds(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+    pub fn is_obstacle(&self, x: i64, y: i64) -> bool {
+        self.rows
+            .get(&y)
+            .is_some_and(|xs| xs.binary_search(&x).is_ok())
+    }
+    /// The cell where a guard walking from `(x, y)` stops in front of the next obstacle, or `None`
+    /// if it walks off the map.
+    pub fn stop(
+        &self,
+        x: i64,
+        y: i64,
+        direction: Direction,
+        extra_obstacle: Option<(i64, i64)>,
+    ) -> Option<(i64, i64)> {
+        let (dx, dy) = direction.to_movement();
+        let (dx, dy) = (dx as i64, dy as i64);
+        // work along the line the guard walks on: `along` is the coordinate that changes
+        let (line, along, obstacles) = match dx {
+            0 => (x, y, self.columns.get(&x)),
+            _ => (y, x, self.rows.get(&y)),
+        };
+        let step = dx + dy;
+        let mut next = obstacles.and_then(|obstacles| match step {
+            1 => obstacles
+                .get(obstacles.partition_point(|&o| o <= along))
+                .copied(),
+            _ => obstacles
+                .partition_point(|&o| o < along)
+                .checked_sub(1)
+                .map(|i| obstacles[i]),
+        });
+        if let Some((obstacle_x, obstacle_y)) = extra_obstacle {
+            let (obstacle_line, obstacle_along) = match dx {
+                0 => (obstacle_x, obstacle_y),
+                _ => (obstacle_y, obstacle_x),
+            };
+            if obstacle_line == line
+                && (obstacle_along - along) * step > 0
+                && next.is_none_or(|n| (obstacle_along - n) * step < 0)
+            {
+                next = Some(obstacle_along);
+            }
+        }
+        next.map(|n| match dx {
+            0 => (x, n - step),
+            _ => (n - step, y),
+        })
+    }
+    fn edge(&self, x: i64, y: i64, direction: Direction) -> (i64, i64) {
+        match direction {
+            Direction::Up => (x, 0),
+            Direction::Right => (self.width - 1, y),
+            Direction::Down => (x, self.height - 1),
+            Direction::Left => (0, y),
+        }
+    }
+    /// The straight walks between turns, from the start to the exit or the repeated turn.
+    fn segments(&self, extra_obstacle: Option<(i64, i64)>) -> (Vec<Segment>, SparseResult) {
+        let mut states = HashSet::new();
+        let mut segments = Vec::new();
+        let (mut x, mut y, mut direction) = (self.x, self.y, self.direction);
+        let (mut steps, mut turns) = (0, 0);
+        loop {
+            let stop = self.stop(x, y, direction, extra_obstacle);
+            let (end_x, end_y) = stop.unwrap_or_else(|| self.edge(x, y, direction));
+            steps += x.abs_diff(end_x) + y.abs_diff(end_y);
+            segments.push(((x, y), (end_x, end_y)));
+            (x, y) = (end_x, end_y);
+            if stop.is_none() {
+                let result = SparseResult::Done {
+                    distinct: distinct_cells(&segments),
+                    steps,
+                    turns,
+                    exit: ((x, y), direction),
+                };
+                return (segments, result);
+            }
+            if !states.insert((x, y, direction.index(), self.policy.phase(turns))) {
+                return (segments, SparseResult::Loop { steps, turns });
+            }
+            direction = self.policy.turn(direction, turns);
+            turns += 1;
+        }
+    }
+}
+
+/// Cells covered by a set of horizontal and vertical segments (single cells count as horizontal).
+fn distinct_cells(segments: &[Segment]) -> u64 {
+    fn merge(intervals: &mut Vec<(i64, i64)>) {
+        intervals.sort();
+        let mut merged: Vec<(i64, i64)> = Vec::with_capacity(intervals.len());
+        for &(start, end) in intervals.iter() {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+    let mut rows: BTreeMap<i64, Vec<(i64, i64)>> = BTreeMap::new();
+    let mut columns: BTreeMap<i64, Vec<(i64, i64)>> = BTreeMap::new();
+    for &((x1, y1), (x2, y2)) in segments {
+        if y1 == y2 {
+            rows.entry(y1).or_default().push((x1.min(x2), x1.max(x2)));
+        } else {
+            columns
+                .entry(x1)
+                .or_default()
+                .push((y1.min(y2), y1.max(y2)));
+        }
+    }
+    let length = |intervals: &Vec<(i64, i64)>| -> u64 {
+        intervals
+            .iter()
+            .map(|(start, end)| end.abs_diff(*start) + 1)
+            .sum()
+    };
+    rows.values_mut().for_each(merge);
+    columns.values_mut().for_each(merge);
+    let mut crossings = 0;
+    for (&x, intervals) in &columns {
+        for &(y1, y2) in intervals {
+            crossings += rows
+                .range(y1..=y2)
+                .filter(|(_, row)| row.iter().any(|&(x1, x2)| x1 <= x && x <= x2))
+                .count() as u64;
+        }
+    }
+    rows.values().map(length).sum::<u64>() + columns.values().map(length).sum::<u64>() - crossings
+}
+
+pub fn run_sparse_simulation(
+    game: &SparseGame,
+    extra_obstacle: Option<(i64, i64)>,
+) -> SparseResult {
+    game.segments(extra_obstacle).1
+}
+
+/// `find_loops_fast` for sparse maps: only cells on the original patrol are tried, and each try
+/// costs one binary search per turn rather than one step per cell.
+pub fn find_loops_sparse(game: &SparseGame) -> u64 {
+    let (segments, result) = game.segments(None);
+    let mut positions = HashSet::new();
+    for &((x1, y1), (x2, y2)) in &segments {
+        let length = x1.abs_diff(x2).max(y1.abs_diff(y2)) as i64;
+        let (dx, dy) = ((x2 - x1).signum(), (y2 - y1).signum());
+        positions.extend((0..=length).map(|t| (x1 + t * dx, y1 + t * dy)));
+    }
+    let unreached = match result {
+        SparseResult::Loop { .. } => {
+            (game.width * game.height) as u64 - game.obstacles as u64 - positions.len() as u64
+        }
+        SparseResult::Done { .. } => 0,
+    };
+    positions.remove(&(game.x, game.y));
+    unreached
+        + positions
+            .into_par_iter()
+            .filter(|&pos| {
+                matches!(
+                    run_sparse_simulation(game, Some(pos)),
+                    SparseResult::Loop { .. }
+                )
+            })
+            .count() as u64
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = r#"....#.....
+.........#
+..........
+..#.......
+.......#..
+..........
+.#..^.....
+........#.
+#.........
+......#..."#;
+
+/// Checks `patrol_loops` and `loops_with_jumps` against `run_simulation` for an obstacle on every
+/// floor cell but the start.
+#[cfg(test)]
+fn assert_loop_search_agrees(game: &Game) {
+    let table = JumpTable::new(game);
+    for y in 0..game.height() {
+        for x in 0..game.width() {
+            if game.at(x, y) == Tile::Floor && (x, y) != (game.x, game.y) {
+                let loops = run_simulation(game.clone(), Some((x, y))).is_loop();
+                let message = format!("{:?} with obstacle at {:?}", game.policy, (x, y));
+                assert_eq!(patrol_loops(game, Some((x, y))), loops, "{}", message);
+                assert_eq!(
+                    loops_with_jumps(game, &table, Some((x, y))),
+                    loops,
+                    "{}",
+                    message
+                );
+            }
+        }
+    }
+}
+
 #[test]
-fn day() -> Result<()> {
-    let data1 = r#"
+fn test_looping_patrol() -> Result<()> {
+    // turning right, the guard walks the same rectangle forever, so an obstacle on any of the 16
+    // floor cells it never reaches keeps it looping
+    let data = r#"
+.#....
+.....#
+.^....
+#.....
+....#.
+"#
+    .trim();
+    let game = read_game_start(data)?;
+    assert_eq!(patrol_positions(&game).len(), 10);
+    let table = JumpTable::new(&game);
+    assert!(loops_with_jumps(&game, &table, None));
+    assert_eq!(find_loops(game.clone()), 16);
+    assert_eq!(find_loops_fast(game), 16);
+    Ok(())
+}
+
+#[test]
+fn test_jump_table() -> Result<()> {
+    let game = read_game_start(EXAMPLE)?;
+    assert_eq!(patrol_positions(&game).len(), 41);
+    let table = JumpTable::new(&game);
+    assert_eq!(table.jump(4, 6, Direction::Up), Jump::Turn(4, 1));
+    assert_eq!(table.jump(4, 1, Direction::Right), Jump::Turn(8, 1));
+    assert_eq!(table.jump(4, 6, Direction::Down), Jump::Exit);
+    assert!(!loops_with_jumps(&game, &table, None));
+    assert!(loops_with_jumps(&game, &table, Some((3, 6))));
+    assert!(!loops_with_jumps(&game, &table, Some((4, 2))));
+    assert_eq!(find_loops_fast(game), 6);
+
+    let game = read_game_start(&std::fs::read_to_string("input/day6")?)?;
+    assert_eq!(patrol_positions(&game).len(), 4977);
+    assert_eq!(find_loops_fast(game), 1729);
+    Ok(())
+}
+
+#[test]
+fn test_guard_steps() -> Result<()> {
+    let game = read_game_start(EXAMPLE)?;
+    let mut steps = guard_steps(game.clone(), None);
+    assert_eq!(
+        steps.next(),
+        Some(GuardState {
+            x: 4,
+            y: 6,
+            direction: Direction::Up,
+            turned: false
+        })
+    );
+    assert_eq!(
+        steps.nth(4).map(|s| (s.x, s.y, s.turned)),
+        Some((4, 1, false))
+    );
+    assert_eq!(
+        steps.next(),
+        Some(GuardState {
+            x: 4,
+            y: 1,
+            direction: Direction::Right,
+            turned: true
+        })
+    );
+    assert_eq!(
+        guard_steps(game.clone(), None).filter(|s| s.turned).count(),
+        10
+    );
+    assert_eq!(
+        guard_steps(game.clone(), None).last().map(|s| (s.x, s.y)),
+        Some((7, 9))
+    );
+    assert_eq!(guard_steps(game, Some((3, 6))).take(1000).count(), 1000);
+    Ok(())
+}
+
+#[test]
+fn test_guards_and_policies() -> Result<()> {
+    let guards = read_guards("..#.\n>..v\n....\n<..#\n.^..")?;
+    assert_eq!(
+        guards
+            .iter()
+            .map(|g| (g.x, g.y, g.direction))
+            .collect::<Vec<_>>(),
+        vec![
+            (0, 1, Direction::Right),
+            (3, 1, Direction::Down),
+            (0, 3, Direction::Left),
+            (1, 4, Direction::Up),
+        ]
+    );
+    assert!(guards.iter().all(|g| g.at(3, 1) == Tile::Floor));
+    assert!(read_game_start("..#.\n>..v").is_err());
+    assert!(read_game_start("..#.\n....").is_err());
+    assert_eq!(read_game_start("..#.\n..<.")?.direction, Direction::Left);
+
+    // `<` walks straight off the map
+    let result = run_simulation(guards[2].clone(), None);
+    assert!(matches!(
+        result,
+        SimulationResult::Done {
+            distinct: 1,
+            exit: ((0, 3), Direction::Left),
+            ..
+        }
+    ));
+
+    // bouncing between two obstacles
+    let game = read_game_start("#.>.#")?.with_policy(TurnPolicy::Reverse);
+    assert!(run_simulation(game.clone(), None).is_loop());
+    assert!(loops_with_jumps(&game, &JumpTable::new(&game), None));
+    assert_eq!(find_loops_fast(game.clone()), find_loops(game));
+
+    let data = EXAMPLE;
+    for policy in [
+        TurnPolicy::Right,
+        TurnPolicy::Left,
+        TurnPolicy::Alternate,
+        TurnPolicy::Reverse,
+    ] {
+        let game = read_game_start(data)?.with_policy(policy);
+        assert_loop_search_agrees(&game);
+        assert_eq!(
+            find_loops_sparse(&game.to_sparse()?) as usize,
+            find_loops_fast(game.clone())
+        );
+        assert_eq!(find_loops_fast(game.clone()), find_loops(game));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_tiles() -> Result<()> {
+    let err = read_game_start("..#.\n.^x.").err().unwrap();
+    assert_eq!(err.to_string(), "unknown tile 'x' at (2, 1)");
+    let err = read_game_start("..#.\n.^.").err().unwrap();
+    assert_eq!(err.to_string(), "row 1 has width 3, expected 4");
+    let err = read_game_start("..1.\n.^..").err().unwrap();
+    assert_eq!(err.to_string(), "portal '1' appears 1 times, expected 2");
+
+    let game = read_game_start("#.→.\n.#.↑\n.^..")?;
+    assert_eq!(game.at(2, 0), Tile::OneWay(Direction::Right));
+    assert!(game.blocks(3, 1, Direction::Left));
+    assert!(!game.blocks(3, 1, Direction::Up));
+    assert!(!game.is_plain());
+    assert!(game.to_sparse().is_err());
+
+    // the door above lets the guard through
+    let game = read_game_start("....\n....\n.↑↓.\n.^..")?;
+    assert_eq!(
+        guard_steps(game.clone(), None)
+            .map(|s| (s.x, s.y))
+            .collect::<Vec<_>>(),
+        vec![(1, 3), (1, 2), (1, 1), (1, 0)]
+    );
+    // a door facing the other way is an obstacle
+    let game = read_game_start("#...\n.←..\n.^..")?;
+    let result = run_simulation(game, None);
+    assert!(matches!(
+        result,
+        SimulationResult::Done {
+            exit: ((3, 2), Direction::Right),
+            ..
+        }
+    ));
+
+    // walking into portal 1 comes out at the other 1 and keeps going right
+    let game = read_game_start("....\n>.1.\n1...")?;
+    assert_eq!(
+        guard_steps(game.clone(), None)
+            .map(|s| (s.x, s.y))
+            .collect::<Vec<_>>(),
+        vec![(0, 1), (1, 1), (0, 2), (1, 2), (2, 2), (3, 2)]
+    );
+    // two portals facing each other along a row loop without ever turning
+    let game = read_game_start("1.>.1")?;
+    assert!(run_simulation(game.clone(), None).is_loop());
+    assert!(loops_with_jumps(&game, &JumpTable::new(&game), None));
+
+    let data = r#"
 ....#.....
 .........#
 ..........
 ..#.......
 .......#..
-..........
+..→....↑..
 .#..^.....
+1.......#.
+#.........
+......#1..
+"#
+    .trim();
+    for policy in [TurnPolicy::Right, TurnPolicy::Alternate] {
+        let game = read_game_start(data)?.with_policy(policy);
+        assert_loop_search_agrees(&game);
+        assert_eq!(find_loops(game.clone()), 2);
+        assert_eq!(find_loops_fast(game.clone()), find_loops(game));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_optimize_obstacles() -> Result<()> {
+    let data = EXAMPLE;
+    let game = read_game_start(data)?;
+    let free: Vec<_> = (0..game.height())
+        .cartesian_product(0..game.width())
+        .map(|(y, x)| (x, y))
+        .filter(|&(x, y)| game.at(x, y) == Tile::Floor && (x, y) != (game.x, game.y))
+        .collect();
+
+    let placement = optimize_obstacles(&game, 1, Objective::SmallestLoop).unwrap();
+    let smallest = free
+        .iter()
+        .filter_map(|&pos| match run_simulation(game.clone(), Some(pos)) {
+            SimulationResult::Loop { cycle, .. } => Some(cycle.len()),
+            _ => None,
+        })
+        .min();
+    assert_eq!(placement.obstacles.len(), 1);
+    let SimulationResult::Loop { cycle, .. } = &placement.result else {
+        panic!("expected a loop");
+    };
+    assert_eq!(Some(cycle.len()), smallest);
+    assert_eq!(
+        placement.result,
+        run_simulation(game.clone(), Some(placement.obstacles[0]))
+    );
+
+    let longest = free
+        .iter()
+        .filter_map(|&pos| match run_simulation(game.clone(), Some(pos)) {
+            SimulationResult::Done { steps, .. } => Some(steps),
+            _ => None,
+        })
+        .max();
+    let placement = optimize_obstacles(&game, 1, Objective::LongestPatrol).unwrap();
+    assert!(
+        matches!(placement.result, SimulationResult::Done { steps, .. } if Some(steps) == longest)
+    );
+
+    // two obstacles against every pair of free cells
+    let pairs: Vec<_> = free
+        .iter()
+        .tuple_combinations()
+        .map(|(&a, &b)| vec![a, b])
+        .collect();
+    for objective in [Objective::LongestPatrol, Objective::SmallestLoop] {
+        let brute_force = free
+            .iter()
+            .map(|&pos| vec![pos])
+            .chain(pairs.iter().cloned())
+            .chain([vec![]])
+            .filter_map(|obstacles| {
+                objective.cost(&run_simulation(game.with_obstacles(&obstacles), None))
+            })
+            .min();
+        let placement = optimize_obstacles(&game, 2, objective).unwrap();
+        assert!(placement.obstacles.len() <= 2);
+        assert_eq!(objective.cost(&placement.result), brute_force);
+        assert_eq!(
+            placement.result,
+            run_simulation(game.with_obstacles(&placement.obstacles), None)
+        );
+    }
+
+    // nothing to improve without any budget
+    let placement = optimize_obstacles(&game, 0, Objective::LongestPatrol).unwrap();
+    assert!(placement.obstacles.is_empty());
+    assert!(optimize_obstacles(&game, 0, Objective::SmallestLoop).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_render() -> Result<()> {
+    let data = EXAMPLE;
+    let game = read_game_start(data)?;
+    let result = run_simulation(game.clone(), Some((3, 6)));
+    assert_eq!(
+        render(&game, result.path(), Some((3, 6))),
+        r#"
+....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
 ........#.
 #.........
 ......#...
 "#
-    .trim();
+        .trim()
+    );
+    let result = run_simulation(game.clone(), Some((6, 7)));
+    assert_eq!(
+        render(&game, result.path(), Some((6, 7))),
+        r#"
+....#.....
+....+---+#
+....|...|.
+..#.|...|.
+..+-+-+#|.
+..|.|.|.|.
+.#+-^-+-+.
+......O.#.
+#.........
+......#...
+"#
+        .trim()
+    );
+
+    let image = render_ppm(&game, &result.path()[..3], Some((6, 7)), 2);
+    let header = b"P6\n20 20\n255\n";
+    assert_eq!(&image[..header.len()], header);
+    assert_eq!(image.len(), header.len() + 20 * 20 * 3);
+    // the guard two steps up from the start, at cell (4, 4)
+    let pixel = header.len() + (4 * 2 * 20 + 4 * 2) * 3;
+    assert_eq!(image[pixel..pixel + 3], [220, 40, 40]);
+
+    let directory = std::env::temp_dir().join("aoc-2024-day6-frames");
+    let frames = write_ppm_frames(&game, Some((6, 7)), &directory, 1)?;
+    assert_eq!(frames, result.path().len());
+    assert!(directory
+        .join(format!("frame_{:05}.ppm", frames - 1))
+        .exists());
+    std::fs::remove_dir_all(directory)?;
+    Ok(())
+}
+
+#[test]
+fn test_sparse() -> Result<()> {
+    // up into an obstacle, then right off a map millions of cells wide
+    let game = SparseGame::new(
+        3_000_000,
+        2_000_000,
+        (1_000_000, 1_500_000, Direction::Up),
+        [(1_000_000, 10), (5, 5)],
+    )?;
+    assert!(game.is_obstacle(5, 5));
+    assert!(!game.is_obstacle(5, 6));
+    assert_eq!(
+        run_sparse_simulation(&game, None),
+        SparseResult::Done {
+            distinct: 1_500_000 - 11 + 1 + 1_999_999,
+            steps: 1_500_000 - 11 + 1_999_999,
+            turns: 1,
+            exit: ((2_999_999, 11), Direction::Right),
+        }
+    );
+    assert_eq!(
+        run_sparse_simulation(&game, Some((2_000_000, 11))),
+        SparseResult::Done {
+            distinct: 1_500_000 - 11 + 1 + 999_999 + 1_999_988,
+            steps: 1_500_000 - 11 + 999_999 + 1_999_988,
+            turns: 2,
+            exit: ((1_999_999, 1_999_999), Direction::Down),
+        }
+    );
+
+    // a rectangle of four obstacles the guard can never leave
+    let obstacles = [
+        (10, 0),
+        (2_000_000, 1),
+        (1_999_999, 1_000_000),
+        (9, 999_999),
+    ];
+    let game = SparseGame::new(4_000_000, 4_000_000, (10, 10, Direction::Up), obstacles)?;
+    assert_eq!(
+        run_sparse_simulation(&game, None),
+        SparseResult::Loop {
+            steps: 9 + 2 * (1_999_989 + 999_998),
+            turns: 4,
+        }
+    );
+    // crossing its own path still counts every cell once
+    let game = SparseGame::new(10, 10, (2, 5, Direction::Up), [(2, 1), (5, 2), (4, 5)])?;
+    assert_eq!(
+        run_sparse_simulation(&game, None),
+        SparseResult::Done {
+            distinct: 4 + 2 + 2 + 3,
+            steps: 3 + 2 + 2 + 4,
+            turns: 3,
+            exit: ((0, 4), Direction::Left),
+        }
+    );
+
+    // starts and obstacles must lie on the map, and the guard cannot start on an obstacle
+    assert!(SparseGame::new(10, 10, (10, 5, Direction::Up), []).is_err());
+    assert!(SparseGame::new(10, 10, (2, -1, Direction::Up), []).is_err());
+    assert!(SparseGame::new(10, 10, (2, 5, Direction::Up), [(2, 5)]).is_err());
+    assert!(SparseGame::new(10, 10, (2, 5, Direction::Right), [(50, 5)]).is_err());
+    assert!(SparseGame::new(10, 10, (2, 5, Direction::Up), [(2, 10)]).is_err());
+    assert!(SparseGame::new(10, 10, (2, 5, Direction::Up), [(-1, 0)]).is_err());
+
+    // the puzzle input gives the same answers as the dense map
+    let game = read_game_start(&std::fs::read_to_string("input/day6")?)?;
+    let SimulationResult::Done {
+        distinct,
+        steps,
+        turns,
+        exit,
+        ..
+    } = run_simulation(game.clone(), None)
+    else {
+        panic!("expected the guard to leave the map");
+    };
+    assert_eq!(
+        run_sparse_simulation(&game.to_sparse()?, None),
+        SparseResult::Done {
+            distinct: distinct as u64,
+            steps: steps as u64,
+            turns,
+            exit: ((exit.0 .0 as i64, exit.0 .1 as i64), exit.1),
+        }
+    );
+    assert_eq!(find_loops_sparse(&game.to_sparse()?), 1729);
+    Ok(())
+}
+
+#[test]
+fn day() -> Result<()> {
+    let data1 = EXAMPLE;
     let data2 = std::fs::read_to_string("input/day6")?;
 
     let game = read_game_start(data1)?;
     assert_eq!(game.x, 4);
     assert_eq!(game.y, 6);
     let result = run_simulation(game, None);
-    assert_eq!(result, SimulationResult::Done(41));
+    let SimulationResult::Done {
+        distinct,
+        path,
+        steps,
+        turns,
+        exit,
+    } = result
+    else {
+        panic!("expected the guard to leave the map");
+    };
+    assert_eq!(distinct, 41);
+    assert_eq!(steps, 44);
+    assert_eq!(turns, 10);
+    assert_eq!(path.len(), 55);
+    assert_eq!(exit, ((7, 9), Direction::Down));
 
     let game = read_game_start(&data2)?;
     let result = run_simulation(game, None);
-    assert_eq!(result, SimulationResult::Done(4977));
+    assert!(matches!(
+        result,
+        SimulationResult::Done { distinct: 4977, .. }
+    ));
 
     let game = read_game_start(data1)?;
     let result = run_simulation(game, Some((3, 6)));
-    assert_eq!(result, SimulationResult::Loop);
+    let SimulationResult::Loop {
+        path,
+        steps,
+        turns,
+        entry_step,
+        cycle,
+    } = result
+    else {
+        panic!("expected a loop");
+    };
+    assert_eq!(steps + turns + 1, path.len());
+    assert_eq!(path[entry_step], cycle[0]);
+    assert_eq!(cycle.len(), path.len() - entry_step);
+    // the state after the last one in the cycle is the first one again; the guard came in from
+    // the start but now arrives by turning, so only position and direction repeat
+    let game = read_game_start(data1)?;
+    let mut steps = guard_steps(game, Some((3, 6))).skip(entry_step);
+    assert!(steps.by_ref().take(cycle.len()).eq(cycle.iter().copied()));
+    let next = steps.next().unwrap();
+    assert_eq!(
+        (next.x, next.y, next.direction),
+        (cycle[0].x, cycle[0].y, cycle[0].direction)
+    );
+    assert!(cycle.iter().any(|s| (s.x, s.y) == (4, 6)));
 
     let game = read_game_start(data1)?;
     let result = run_simulation(game, Some((6, 7)));
-    assert_eq!(result, SimulationResult::Loop);
+    assert!(result.is_loop());
 
     let game = read_game_start(data1)?;
     let result = run_simulation(game, Some((7, 7)));
-    assert_eq!(result, SimulationResult::Loop);
+    assert!(result.is_loop());
 
     let game = read_game_start(data1)?;
     let loops = find_loops(game);
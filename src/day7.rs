@@ -1,39 +1,369 @@
+use anyhow::{bail, Result};
 use itertools::Itertools;
+use num_bigint::BigUint;
+use num_integer::Roots;
+use num_traits::{
+    checked_pow, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, FromPrimitive, One, ToPrimitive,
+    Zero,
+};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::iter::Sum;
+use std::ops::{BitXor, Rem};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// What equations are evaluated in. Every operation is checked, so a branch that overflows has no
+/// solution instead of panicking or wrapping. `usize` is the default, `u128` and `BigUint` give
+/// more room when results are large, though powers stay capped by `Pow` even for `BigUint`.
+pub trait Number:
+    Clone
+    + Ord
+    + fmt::Display
+    + fmt::Debug
+    + FromStr<Err: fmt::Debug>
+    + Zero
+    + One
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + Rem<Output = Self>
+    + BitXor<Output = Self>
+    + Roots
+    + FromPrimitive
+    + ToPrimitive
+    + Hash
+    + Sum
+    + Send
+    + Sync
+    + 'static
+{
+    /// The number of bits needed to write the number down.
+    fn bits(&self) -> u64;
+}
+
+impl Number for usize {
+    fn bits(&self) -> u64 {
+        (usize::BITS - self.leading_zeros()) as u64
+    }
+}
+
+impl Number for u128 {
+    fn bits(&self) -> u64 {
+        (u128::BITS - self.leading_zeros()) as u64
+    }
+}
+
+impl Number for BigUint {
+    fn bits(&self) -> u64 {
+        BigUint::bits(self)
+    }
+}
 
 #[derive(PartialEq, Debug)]
-pub struct Equation {
-    pub result: usize,
-    pub values: Vec<usize>,
+pub struct Equation<N = usize> {
+    pub result: N,
+    pub values: Vec<N>,
 }
 
-#[derive(Debug)]
-pub enum Operator {
-    Mul,
-    Add,
-    Combine,
+impl<N: fmt::Display> fmt::Display for Equation<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.result, self.values.iter().join(" "))
+    }
 }
 
-fn number_of_digits(n: usize) -> u32 {
-    if n == 0 {
-        return 1;
+fn number_of_digits<N: Number>(mut n: N, base: N) -> u32 {
+    let mut digits = 1;
+    while n >= base {
+        n = n.checked_div(&base).unwrap();
+        digits += 1;
     }
-    n.ilog10() + 1
+    digits
 }
 
-impl Operator {
-    pub fn apply(&self, lhs: usize, rhs: usize) -> usize {
-        match self {
-            Operator::Mul => lhs * rhs,
-            Operator::Add => lhs + rhs,
-            Operator::Combine => lhs * 10_usize.pow(number_of_digits(rhs)) + rhs,
+/// What `lhs` has to be for `apply(lhs, rhs)` to give a certain result.
+#[derive(Debug, PartialEq)]
+pub enum Inverse<N = usize> {
+    Lhs(N),
+    Any,
+    Impossible,
+    /// No cheap way back, the solver has to try the left side forwards.
+    Unknown,
+}
+
+pub trait Operator<N = usize>: fmt::Display + Sync {
+    /// `None` when the result is not defined, like dividing by zero, or does not fit in `N`.
+    fn apply(&self, lhs: N, rhs: N) -> Option<N>;
+    fn unapply(&self, _result: N, _rhs: N) -> Inverse<N> {
+        Inverse::Unknown
+    }
+    /// Whether `apply(lhs, rhs) >= lhs` for every `rhs` but zero, which lets the solver cut off
+    /// branches whose target is already too small.
+    fn grows(&self) -> bool {
+        false
+    }
+    /// How tightly the operator binds with `Evaluation::Precedence`, higher binds first.
+    fn precedence(&self) -> u8;
+    fn right_associative(&self) -> bool {
+        false
+    }
+}
+
+pub struct Mul;
+pub struct Add;
+pub struct Sub;
+pub struct Div;
+/// Raises `lhs` to the power of `rhs`. Powers that need more than `max_bits` bits count as
+/// overflowing, even for `BigUint`, which would otherwise try to allocate whatever a huge exponent
+/// asks for.
+pub struct Pow {
+    max_bits: u64,
+}
+pub struct Xor;
+/// Appends the digits of `rhs` in `base` to `lhs`.
+pub struct Combine {
+    base: usize,
+}
+
+impl Pow {
+    pub const DEFAULT: Pow = Pow { max_bits: 1 << 16 };
+
+    /// A larger `max_bits` allows bigger powers with `BigUint`. Evaluating left to right, the bits
+    /// of an equation's result are enough when every operator in the set grows and no value is
+    /// zero, since nothing after the power can make it smaller.
+    pub fn new(max_bits: u64) -> Pow {
+        Pow { max_bits }
+    }
+}
+
+impl Combine {
+    pub const DECIMAL: Combine = Combine { base: 10 };
+
+    pub fn new(base: usize) -> Result<Combine> {
+        if base < 2 {
+            bail!("cannot combine digits in base {}", base);
         }
+        Ok(Combine { base })
+    }
+
+    /// What `lhs` gets multiplied by to make room for the digits of `rhs`.
+    fn shift<N: Number>(&self, rhs: N) -> Option<N> {
+        let base = N::from_usize(self.base)?;
+        checked_pow(base.clone(), number_of_digits(rhs, base) as usize)
     }
 }
 
-pub const OPERATORS_PART1: &[Operator] = &[Operator::Mul, Operator::Add];
-pub const OPERATORS_PART2: &[Operator] = &[Operator::Mul, Operator::Add, Operator::Combine];
+impl<N: Number> Operator<N> for Mul {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        lhs.checked_mul(&rhs)
+    }
+    fn unapply(&self, result: N, rhs: N) -> Inverse<N> {
+        if rhs.is_zero() {
+            return match result.is_zero() {
+                true => Inverse::Any,
+                false => Inverse::Impossible,
+            };
+        }
+        match (result.clone() % rhs.clone()).is_zero() {
+            true => Inverse::Lhs(result.checked_div(&rhs).unwrap()),
+            false => Inverse::Impossible,
+        }
+    }
+    fn grows(&self) -> bool {
+        true
+    }
+    fn precedence(&self) -> u8 {
+        3
+    }
+}
+
+impl<N: Number> Operator<N> for Add {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        lhs.checked_add(&rhs)
+    }
+    fn unapply(&self, result: N, rhs: N) -> Inverse<N> {
+        match result.checked_sub(&rhs) {
+            Some(lhs) => Inverse::Lhs(lhs),
+            None => Inverse::Impossible,
+        }
+    }
+    fn grows(&self) -> bool {
+        true
+    }
+    fn precedence(&self) -> u8 {
+        2
+    }
+}
+
+impl<N: Number> Operator<N> for Sub {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        lhs.checked_sub(&rhs)
+    }
+    fn unapply(&self, result: N, rhs: N) -> Inverse<N> {
+        match result.checked_add(&rhs) {
+            Some(lhs) => Inverse::Lhs(lhs),
+            None => Inverse::Impossible,
+        }
+    }
+    fn precedence(&self) -> u8 {
+        2
+    }
+}
+
+/// Rounds down, so there is no single `lhs` to go back to.
+impl<N: Number> Operator<N> for Div {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        lhs.checked_div(&rhs)
+    }
+    fn precedence(&self) -> u8 {
+        3
+    }
+}
 
-pub fn read_equations(input: &str) -> Vec<Equation> {
+impl<N: Number> Operator<N> for Pow {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        let exponent = rhs.to_usize()?;
+        // lhs is at least 2^(bits - 1), so this is the fewest bits the power can have
+        if lhs > N::one() && (lhs.bits() - 1).checked_mul(exponent as u64)? + 1 > self.max_bits {
+            return None;
+        }
+        checked_pow(lhs, exponent).filter(|power| power.bits() <= self.max_bits)
+    }
+    fn unapply(&self, result: N, rhs: N) -> Inverse<N> {
+        if rhs.is_zero() {
+            return match result.is_one() {
+                true => Inverse::Any,
+                false => Inverse::Impossible,
+            };
+        }
+        // zero and one are the only roots of themselves for exponents too big to take a root for
+        let root = match rhs.to_u32() {
+            Some(exponent) => result.nth_root(exponent),
+            None => result.clone(),
+        };
+        match self.apply(root.clone(), rhs) == Some(result) {
+            true => Inverse::Lhs(root),
+            false => Inverse::Impossible,
+        }
+    }
+    fn grows(&self) -> bool {
+        true
+    }
+    fn precedence(&self) -> u8 {
+        4
+    }
+    fn right_associative(&self) -> bool {
+        true
+    }
+}
+
+impl<N: Number> Operator<N> for Xor {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        Some(lhs ^ rhs)
+    }
+    fn unapply(&self, result: N, rhs: N) -> Inverse<N> {
+        Inverse::Lhs(result ^ rhs)
+    }
+    fn precedence(&self) -> u8 {
+        1
+    }
+}
+
+impl<N: Number> Operator<N> for Combine {
+    fn apply(&self, lhs: N, rhs: N) -> Option<N> {
+        lhs.checked_mul(&self.shift(rhs.clone())?)?
+            .checked_add(&rhs)
+    }
+    fn unapply(&self, result: N, rhs: N) -> Inverse<N> {
+        let Some(shift) = self.shift(rhs.clone()) else {
+            return Inverse::Impossible;
+        };
+        match result.clone() % shift.clone() == rhs {
+            true => Inverse::Lhs(result.checked_div(&shift).unwrap()),
+            false => Inverse::Impossible,
+        }
+    }
+    fn grows(&self) -> bool {
+        true
+    }
+    fn precedence(&self) -> u8 {
+        5
+    }
+}
+impl fmt::Display for Mul {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "*")
+    }
+}
+
+impl fmt::Display for Add {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+")
+    }
+}
+
+impl fmt::Display for Sub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "-")
+    }
+}
+
+impl fmt::Display for Div {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/")
+    }
+}
+
+impl fmt::Display for Pow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "^")
+    }
+}
+
+impl fmt::Display for Xor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "xor")
+    }
+}
+
+impl fmt::Display for Combine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.base {
+            10 => write!(f, "||"),
+            base => write!(f, "||{}", base),
+        }
+    }
+}
+
+pub const OPERATORS_PART1: &[&dyn Operator] = &[&Mul, &Add];
+pub const OPERATORS_PART2: &[&dyn Operator] = &[&Mul, &Add, &Combine::DECIMAL];
+
+/// Parses a set of operators like `* + || ||2 - / ^ xor`, where `||2` combines in base 2.
+pub fn operator_set<N: Number>(spec: &str) -> Result<Vec<Box<dyn Operator<N>>>> {
+    spec.split_whitespace()
+        .map(|token| -> Result<Box<dyn Operator<N>>> {
+            Ok(match token {
+                "*" => Box::new(Mul),
+                "+" => Box::new(Add),
+                "-" => Box::new(Sub),
+                "/" => Box::new(Div),
+                "^" => Box::new(Pow::DEFAULT),
+                "xor" => Box::new(Xor),
+                "||" => Box::new(Combine::DECIMAL),
+                _ => match token.strip_prefix("||").and_then(|b| b.parse().ok()) {
+                    Some(base) => Box::new(Combine::new(base)?),
+                    None => bail!("unknown operator '{}'", token),
+                },
+            })
+        })
+        .collect()
+}
+
+pub fn read_equations<N: Number>(input: &str) -> Vec<Equation<N>> {
     input
         .lines()
         .map(|l| {
@@ -46,41 +376,477 @@ pub fn read_equations(input: &str) -> Vec<Equation> {
         .collect()
 }
 
-pub fn evaluate(values: &[usize], operators: &[&Operator]) -> usize {
-    let mut lhs = values[0];
+pub fn evaluate<N: Number>(values: &[N], operators: &[&dyn Operator<N>]) -> Option<N> {
+    let mut lhs = values[0].clone();
     for (value_index, operator) in operators.iter().enumerate() {
-        lhs = operator.apply(lhs, values[value_index + 1]);
+        lhs = operator.apply(lhs, values[value_index + 1].clone())?;
     }
-    lhs
+    Some(lhs)
 }
 
-pub fn equation_is_true(equation: &Equation, operators: &[Operator]) -> bool {
-    for operators in std::iter::repeat(operators)
-        .take(equation.values.len() - 1)
+pub fn equation_is_true<N: Number>(equation: &Equation<N>, operators: &[&dyn Operator<N>]) -> bool {
+    for operators in std::iter::repeat_n(operators.iter().copied(), equation.values.len() - 1)
         .multi_cartesian_product()
     {
-        if evaluate(&equation.values, &operators) == equation.result {
+        if evaluate(&equation.values, &operators).as_ref() == Some(&equation.result) {
             return true;
         }
     }
     false
 }
 
-pub fn total_calibration_result(equations: &[Equation], operators: &[Operator]) -> usize {
+/// Works right to left, undoing the last operator to get the result the remaining values have to
+/// produce, so only divisors, differences and matching suffixes are followed. Operators without an
+/// inverse get their left side evaluated forwards. With no zeros about and only growing
+/// operators, a target below the first value cuts the branch.
+/// Stops after `limit` operator sequences have been found.
+fn find_solutions<'a, N: Number>(
+    equation: &Equation<N>,
+    operators: &'a [&'a dyn Operator<N>],
+    limit: usize,
+) -> Vec<Vec<&'a dyn Operator<N>>> {
+    struct Search<'a, 'b, N> {
+        operators: &'a [&'a dyn Operator<N>],
+        grows: bool,
+        limit: usize,
+        // operators chosen so far, last one first
+        suffix: Vec<&'a dyn Operator<N>>,
+        found: &'b mut Vec<Vec<&'a dyn Operator<N>>>,
+    }
+    impl<'a, N: Number> Search<'a, '_, N> {
+        fn solve(&mut self, target: N, values: &[N]) {
+            let (last, rest) = values.split_last().unwrap();
+            if rest.is_empty() {
+                if target == *last {
+                    self.found.push(self.suffix.iter().rev().copied().collect());
+                }
+                return;
+            }
+            if self.grows && target < rest[0] {
+                return;
+            }
+            for &operator in self.operators {
+                if self.found.len() >= self.limit {
+                    return;
+                }
+                match operator.unapply(target.clone(), last.clone()) {
+                    Inverse::Lhs(lhs) => {
+                        self.suffix.push(operator);
+                        self.solve(lhs, rest);
+                        self.suffix.pop();
+                    }
+                    Inverse::Impossible => {}
+                    inverse => {
+                        // any left side will do, or there is no way back and the left side has to
+                        // be evaluated for every choice of the operators before
+                        let prefixes =
+                            std::iter::repeat_n(self.operators.iter().copied(), rest.len() - 1)
+                                .multi_cartesian_product()
+                                .filter(|prefix| match evaluate(rest, prefix) {
+                                    Some(lhs) => {
+                                        matches!(inverse, Inverse::Any)
+                                            || operator.apply(lhs, last.clone()).as_ref()
+                                                == Some(&target)
+                                    }
+                                    None => false,
+                                })
+                                .take(self.limit - self.found.len());
+                        for mut prefix in prefixes {
+                            prefix.push(operator);
+                            prefix.extend(self.suffix.iter().rev());
+                            self.found.push(prefix);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let mut found = Vec::new();
+    Search {
+        operators,
+        grows: !equation.values.iter().any(N::is_zero) && operators.iter().all(|o| o.grows()),
+        limit,
+        suffix: Vec::new(),
+        found: &mut found,
+    }
+    .solve(equation.result.clone(), &equation.values);
+    found
+}
+
+pub fn can_be_true<N: Number>(equation: &Equation<N>, operators: &[&dyn Operator<N>]) -> bool {
+    !find_solutions(equation, operators, 1).is_empty()
+}
+
+/// One operator sequence that makes the equation true.
+pub fn solve<'a, N: Number>(
+    equation: &Equation<N>,
+    operators: &'a [&'a dyn Operator<N>],
+) -> Option<Vec<&'a dyn Operator<N>>> {
+    find_solutions(equation, operators, 1).pop()
+}
+
+/// Every operator sequence that makes the equation true.
+pub fn solve_all<'a, N: Number>(
+    equation: &Equation<N>,
+    operators: &'a [&'a dyn Operator<N>],
+) -> Vec<Vec<&'a dyn Operator<N>>> {
+    find_solutions(equation, operators, usize::MAX)
+}
+
+/// Renders a solution like `81 * 40 + 27 = 3267`.
+pub fn render_solution<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+) -> String {
+    let mut rendered = equation.values[0].to_string();
+    for (operator, value) in operators.iter().zip(&equation.values[1..]) {
+        rendered += &format!(" {} {}", operator, value);
+    }
+    format!("{} = {}", rendered, equation.result)
+}
+
+/// How the operators of an equation combine its values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Evaluation {
+    /// Strictly left to right, as in the puzzle.
+    LeftToRight,
+    /// Higher `Operator::precedence` first, like `2 + 3 * 4 = 14`.
+    Precedence,
+    /// Any way of placing parentheses.
+    AnyParenthesization,
+}
+
+/// Evaluates with operator precedence, by keeping the operators that still wait for their right
+/// side on a stack.
+pub fn evaluate_precedence<N: Number>(values: &[N], operators: &[&dyn Operator<N>]) -> Option<N> {
+    fn reduce<N: Number>(operands: &mut Vec<N>, operator: &dyn Operator<N>) -> Option<()> {
+        let rhs = operands.pop().unwrap();
+        let lhs = operands.pop().unwrap();
+        operands.push(operator.apply(lhs, rhs)?);
+        Some(())
+    }
+    let mut operands = vec![values[0].clone()];
+    let mut pending: Vec<&dyn Operator<N>> = Vec::new();
+    for (&operator, value) in operators.iter().zip(&values[1..]) {
+        while let Some(&top) = pending.last() {
+            let binds_first = top.precedence() > operator.precedence()
+                || (top.precedence() == operator.precedence() && !operator.right_associative());
+            if !binds_first {
+                break;
+            }
+            reduce(&mut operands, pending.pop().unwrap())?;
+        }
+        pending.push(operator);
+        operands.push(value.clone());
+    }
+    while let Some(operator) = pending.pop() {
+        reduce(&mut operands, operator)?;
+    }
+    operands.pop()
+}
+
+/// Every value a range of the expression can take, with how the last operator splits the range to
+/// get it: `(split, lhs, rhs)`, or `None` for a single value.
+type Parenthesizations<N> = Vec<Vec<BTreeMap<N, Option<(usize, N, N)>>>>;
+
+/// Goes over ranges of values from short to long, combining what every split of a range can give.
+fn parenthesizations<N: Number>(
+    values: &[N],
+    operators: &[&dyn Operator<N>],
+) -> Parenthesizations<N> {
+    let n = values.len();
+    let mut ranges: Parenthesizations<N> = vec![vec![BTreeMap::new(); n]; n];
+    for (i, value) in values.iter().enumerate() {
+        ranges[i][i].insert(value.clone(), None);
+    }
+    for length in 1..n {
+        for start in 0..n - length {
+            let end = start + length;
+            let mut results = BTreeMap::new();
+            for split in start..end {
+                for lhs in ranges[start][split].keys() {
+                    for rhs in ranges[split + 1][end].keys() {
+                        if let Some(value) = operators[split].apply(lhs.clone(), rhs.clone()) {
+                            results
+                                .entry(value)
+                                .or_insert_with(|| Some((split, lhs.clone(), rhs.clone())));
+                        }
+                    }
+                }
+            }
+            ranges[start][end] = results;
+        }
+    }
+    ranges
+}
+
+impl Evaluation {
+    /// Every value the expression can come out as, which is at most one unless parentheses can go
+    /// anywhere.
+    pub fn results<N: Number>(&self, values: &[N], operators: &[&dyn Operator<N>]) -> Vec<N> {
+        match self {
+            Evaluation::LeftToRight => evaluate(values, operators).into_iter().collect(),
+            Evaluation::Precedence => evaluate_precedence(values, operators).into_iter().collect(),
+            Evaluation::AnyParenthesization => parenthesizations(values, operators)[0]
+                [values.len() - 1]
+                .keys()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Every operator sequence that makes the equation true when evaluated the given way. With any
+/// parenthesization a sequence counts once, however many ways of placing parentheses work.
+pub fn solutions_with<'a, N: Number>(
+    equation: &Equation<N>,
+    operators: &'a [&'a dyn Operator<N>],
+    evaluation: Evaluation,
+) -> Vec<Vec<&'a dyn Operator<N>>> {
+    match evaluation {
+        Evaluation::LeftToRight => solve_all(equation, operators),
+        _ => std::iter::repeat_n(operators.iter().copied(), equation.values.len() - 1)
+            .multi_cartesian_product()
+            .filter(|operators| {
+                evaluation
+                    .results(&equation.values, operators)
+                    .contains(&equation.result)
+            })
+            .collect(),
+    }
+}
+
+pub fn count_solutions_with<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+    evaluation: Evaluation,
+) -> usize {
+    match evaluation {
+        Evaluation::LeftToRight => count_solutions(equation, operators),
+        _ => solutions_with(equation, operators, evaluation).len(),
+    }
+}
+
+/// Renders parentheses that make the operators give the result, like `2 + (3 * 4) = 14`.
+pub fn render_parenthesized<N: Number>(
+    equation: &Equation<N>,
+    operators: &[&dyn Operator<N>],
+) -> Option<String> {
+    fn render<N: Number>(
+        ranges: &Parenthesizations<N>,
+        operators: &[&dyn Operator<N>],
+        start: usize,
+        end: usize,
+        value: &N,
+        outermost: bool,
+    ) -> String {
+        match &ranges[start][end][value] {
+            None => value.to_string(),
+            Some((split, lhs, rhs)) => {
+                let rendered = format!(
+                    "{} {} {}",
+                    render(ranges, operators, start, *split, lhs, false),
+                    operators[*split],
+                    render(ranges, operators, split + 1, end, rhs, false)
+                );
+                match outermost {
+                    true => rendered,
+                    false => format!("({})", rendered),
+                }
+            }
+        }
+    }
+    let ranges = parenthesizations(&equation.values, operators);
+    let end = equation.values.len() - 1;
+    ranges[0][end].contains_key(&equation.result).then(|| {
+        format!(
+            "{} = {}",
+            render(&ranges, operators, 0, end, &equation.result, true),
+            equation.result
+        )
+    })
+}
+
+/// How many operator sequences make the equation true. Goes left to right and remembers the count
+/// for every running value at every position, as different operators often get to the same value.
+pub fn count_solutions<N: Number>(equation: &Equation<N>, operators: &[&dyn Operator<N>]) -> usize {
+    struct Count<'a, N> {
+        equation: &'a Equation<N>,
+        operators: &'a [&'a dyn Operator<N>],
+        grows: bool,
+        memo: HashMap<(usize, N), usize>,
+    }
+    impl<N: Number> Count<'_, N> {
+        fn count(&mut self, index: usize, value: N) -> usize {
+            if index == self.equation.values.len() {
+                return (value == self.equation.result) as usize;
+            }
+            if self.grows && value > self.equation.result {
+                return 0;
+            }
+            if let Some(&count) = self.memo.get(&(index, value.clone())) {
+                return count;
+            }
+            let rhs = &self.equation.values[index];
+            let count = self
+                .operators
+                .iter()
+                .filter_map(|operator| operator.apply(value.clone(), rhs.clone()))
+                .map(|next| self.count(index + 1, next))
+                .sum();
+            self.memo.insert((index, value), count);
+            count
+        }
+    }
+    Count {
+        equation,
+        operators,
+        grows: !equation.values.iter().any(N::is_zero) && operators.iter().all(|o| o.grows()),
+        memo: HashMap::new(),
+    }
+    .count(1, equation.values[0].clone())
+}
+
+/// The equations with their number of solutions, the most ambiguous first.
+pub fn ambiguity<'a, N: Number>(
+    equations: &'a [Equation<N>],
+    operators: &[&dyn Operator<N>],
+) -> Vec<(&'a Equation<N>, usize)> {
+    equations
+        .iter()
+        .map(|e| (e, count_solutions(e, operators)))
+        .sorted_by_key(|&(_, count)| std::cmp::Reverse(count))
+        .collect()
+}
+
+pub fn ambiguity_table<N: Number>(
+    equations: &[Equation<N>],
+    operators: &[&dyn Operator<N>],
+) -> String {
+    let mut rows = vec![[
+        "solutions".to_string(),
+        "equation".to_string(),
+        "example".to_string(),
+    ]];
+    for (equation, count) in ambiguity(equations, operators) {
+        let example = solve(equation, operators).map(|s| render_solution(equation, &s));
+        rows.push([
+            count.to_string(),
+            equation.to_string(),
+            example.unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+    let widths: Vec<usize> = (0..3)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+        .collect();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:width$}", cell))
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .join("\n")
+}
+
+pub fn total_calibration_result<N: Number>(
+    equations: &[Equation<N>],
+    operators: &[&dyn Operator<N>],
+) -> N {
     equations
         .iter()
-        .filter(|e| equation_is_true(e, operators))
-        .map(|e| e.result)
+        .filter(|e| can_be_true(e, operators))
+        .map(|e| e.result.clone())
         .sum()
 }
 
+/// How one equation fared in a calibration run.
+#[derive(Debug)]
+pub struct Calibrated {
+    pub solvable: bool,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub struct Calibration<N> {
+    pub total: N,
+    /// In the same order as the equations.
+    pub equations: Vec<Calibrated>,
+}
+
+/// Checks the equations in parallel. `progress` gets the number of equations done so far and the
+/// total after each one, from whichever thread finished it.
+pub fn par_calibrate<N: Number>(
+    equations: &[Equation<N>],
+    operators: &[&dyn Operator<N>],
+    progress: impl Fn(usize, usize) + Sync,
+) -> Calibration<N> {
+    let done = AtomicUsize::new(0);
+    let calibrated: Vec<Calibrated> = equations
+        .par_iter()
+        .map(|equation| {
+            let start = Instant::now();
+            let solvable = can_be_true(equation, operators);
+            let elapsed = start.elapsed();
+            progress(done.fetch_add(1, Ordering::Relaxed) + 1, equations.len());
+            Calibrated { solvable, elapsed }
+        })
+        .collect();
+    Calibration {
+        total: equations
+            .iter()
+            .zip(&calibrated)
+            .filter(|(_, c)| c.solvable)
+            .map(|(e, _)| e.result.clone())
+            .sum(),
+        equations: calibrated,
+    }
+}
+
+pub fn par_total_calibration_result<N: Number>(
+    equations: &[Equation<N>],
+    operators: &[&dyn Operator<N>],
+) -> N {
+    par_calibrate(equations, operators, |_, _| {}).total
+}
+
+/// Every equation of one to four `values` with a result below `results`, together with the number
+/// of operator sequences that make it true, found by trying them all.
+#[cfg(test)]
+fn brute_force_equations<'a>(
+    values: &'a [usize],
+    results: usize,
+    operators: &'a [&'a dyn Operator],
+) -> impl Iterator<Item = (Equation, usize)> + 'a {
+    (1..=4)
+        .flat_map(move |length| {
+            std::iter::repeat_n(values.iter().copied(), length).multi_cartesian_product()
+        })
+        .flat_map(move |values| {
+            (0..results).map(move |result| Equation {
+                result,
+                values: values.clone(),
+            })
+        })
+        .map(move |equation| {
+            let count = std::iter::repeat_n(operators.iter().copied(), equation.values.len() - 1)
+                .multi_cartesian_product()
+                .filter(|operators| evaluate(&equation.values, operators) == Some(equation.result))
+                .count();
+            (equation, count)
+        })
+}
+
 #[test]
 fn test_number_of_digits() {
-    assert_eq!(number_of_digits(0), 1);
-    assert_eq!(number_of_digits(1), 1);
-    assert_eq!(number_of_digits(99), 2);
-    assert_eq!(number_of_digits(100), 3);
-    assert_eq!(number_of_digits(9999), 4);
+    assert_eq!(number_of_digits::<usize>(0, 10), 1);
+    assert_eq!(number_of_digits::<usize>(1, 10), 1);
+    assert_eq!(number_of_digits::<usize>(99, 10), 2);
+    assert_eq!(number_of_digits::<usize>(100, 10), 3);
+    assert_eq!(number_of_digits::<usize>(9999, 10), 4);
+    assert_eq!(number_of_digits::<usize>(0b1000, 2), 4);
 }
 
 #[test]
@@ -109,18 +875,373 @@ fn test_equation_is_true() {
 }
 
 #[test]
-fn day() {
-    let data1 = r#"
+fn test_can_be_true() {
+    assert_eq!(Mul.unapply(3267_usize, 27), Inverse::Lhs(121));
+    assert_eq!(Mul.unapply(3267_usize, 40), Inverse::Impossible);
+    assert_eq!(Mul.unapply(0_usize, 0), Inverse::Any);
+    assert_eq!(Add.unapply(3267_usize, 27), Inverse::Lhs(3240));
+    assert_eq!(Add.unapply(20_usize, 27), Inverse::Impossible);
+    let combine = Combine::DECIMAL;
+    assert_eq!(combine.unapply(156_usize, 6), Inverse::Lhs(15));
+    assert_eq!(combine.unapply(156_usize, 56), Inverse::Lhs(1));
+    assert_eq!(combine.unapply(156_usize, 156), Inverse::Lhs(0));
+    assert_eq!(combine.unapply(156_usize, 7), Inverse::Impossible);
+    assert_eq!(combine.unapply(100_usize, 0), Inverse::Lhs(10));
+
+    // the brute force is the reference, including zeros and ones
+    for operators in [OPERATORS_PART1, OPERATORS_PART2] {
+        for (equation, brute_force) in
+            brute_force_equations(&[0, 1, 2, 3, 5, 10, 12], 130, operators)
+        {
+            assert_eq!(
+                can_be_true(&equation, operators),
+                brute_force > 0,
+                "{:?}",
+                equation
+            );
+        }
+    }
+}
+
+#[test]
+fn test_solve() {
+    let equation = Equation {
+        result: 3267,
+        values: vec![81, 40, 27],
+    };
+    let solution = solve(&equation, OPERATORS_PART1).unwrap();
+    assert_eq!(render_solution(&equation, &solution), "81 + 40 * 27 = 3267");
+    assert_eq!(
+        solve_all(&equation, OPERATORS_PART1)
+            .iter()
+            .map(|s| render_solution(&equation, s))
+            .collect::<Vec<_>>(),
+        vec!["81 + 40 * 27 = 3267", "81 * 40 + 27 = 3267"]
+    );
+    let equation = Equation {
+        result: 7290,
+        values: vec![6, 8, 6, 15],
+    };
+    assert!(solve(&equation, OPERATORS_PART1).is_none());
+    assert_eq!(
+        render_solution(&equation, &solve(&equation, OPERATORS_PART2).unwrap()),
+        "6 * 8 || 6 * 15 = 7290"
+    );
+
+    // a zero times anything works for whatever comes before it
+    let equation = Equation {
+        result: 0,
+        values: vec![3, 4, 0],
+    };
+    assert_eq!(
+        solve_all(&equation, OPERATORS_PART1)
+            .iter()
+            .map(|s| render_solution(&equation, s))
+            .collect::<Vec<_>>(),
+        vec!["3 * 4 * 0 = 0", "3 + 4 * 0 = 0"]
+    );
+
+    for (equation, brute_force) in brute_force_equations(&[0, 1, 2, 3, 10], 40, OPERATORS_PART2) {
+        let solutions = solve_all(&equation, OPERATORS_PART2);
+        assert_eq!(solutions.len(), brute_force, "{:?}", equation);
+        assert!(solutions
+            .iter()
+            .all(|s| evaluate(&equation.values, s) == Some(equation.result)));
+    }
+}
+
+#[test]
+fn test_operator_set() {
+    let operators = operator_set::<usize>("* + || ||2 - / ^ xor").unwrap();
+    assert_eq!(operators.iter().join(" "), "* + || ||2 - / ^ xor");
+    assert!(operator_set::<usize>("* %").is_err());
+    assert!(operator_set::<usize>("||1").is_err());
+
+    let binary = Combine::new(2).unwrap();
+    assert_eq!(binary.apply(0b101_usize, 0b11), Some(0b10111));
+    assert_eq!(binary.unapply(0b10111_usize, 0b11), Inverse::Lhs(0b101));
+    assert!(Combine::new(0).is_err());
+    assert!(Combine::new(1).is_err());
+    assert_eq!(Sub.apply(3_usize, 5), None);
+    assert_eq!(Div.apply(7_usize, 2), Some(3));
+    assert_eq!(Div.apply(7_usize, 0), None);
+    assert_eq!(Pow::DEFAULT.unapply(243_usize, 5), Inverse::Lhs(3));
+    assert_eq!(Pow::DEFAULT.unapply(244_usize, 5), Inverse::Impossible);
+    assert_eq!(Pow::DEFAULT.unapply(1_usize, 0), Inverse::Any);
+    assert_eq!(Xor.unapply(6_usize, 3), Inverse::Lhs(5));
+
+    let equation = Equation {
+        result: 3,
+        values: vec![9, 2, 5, 3],
+    };
+    let operators = operator_set::<usize>("- / xor").unwrap();
+    let operators = operators.iter().map(Box::as_ref).collect::<Vec<_>>();
+    assert_eq!(
+        solve_all(&equation, &operators)
+            .iter()
+            .map(|s| render_solution(&equation, s))
+            .collect::<Vec<_>>(),
+        vec!["9 xor 2 - 5 - 3 = 3", "9 / 2 / 5 xor 3 = 3"]
+    );
+
+    // every operator at once against the brute force
+    let operators = operator_set::<usize>("* + - / ^ xor ||2").unwrap();
+    let operators = operators.iter().map(Box::as_ref).collect::<Vec<_>>();
+    for (equation, brute_force) in brute_force_equations(&[0, 1, 2, 3, 7], 50, &operators) {
+        assert_eq!(
+            solve_all(&equation, &operators).len(),
+            brute_force,
+            "{:?}",
+            equation
+        );
+    }
+}
+
+#[test]
+fn test_overflow() {
+    // a huge exponent gives up instead of allocating the power
+    let equations = read_equations::<BigUint>("7: 2 100000000000\n1: 1 100000000000");
+    let operators = operator_set::<BigUint>("^ +").unwrap();
+    let operators = operators.iter().map(Box::as_ref).collect::<Vec<_>>();
+    assert_eq!(count_solutions(&equations[0], &operators), 0);
+    assert!(!equation_is_true(&equations[0], &operators));
+    assert!(!can_be_true(&equations[0], &operators));
+    assert_eq!(count_solutions(&equations[1], &operators), 1);
+    assert!(can_be_true(&equations[1], &operators));
+    let big = |n: u64| BigUint::from(n);
+    assert_eq!(Pow::DEFAULT.apply(big(2), big(100_000)), None);
+    // 2^40000 needs 40001 bits, within the default even though 2 takes two bits to write
+    assert_eq!(
+        Pow::DEFAULT.apply(big(2), big(40_000)),
+        Some(big(1) << 40_000)
+    );
+    assert_eq!(
+        Pow::DEFAULT.apply(big(2), big(65_535)),
+        Some(big(1) << 65_535)
+    );
+    assert_eq!(Pow::DEFAULT.apply(big(2), big(65_536)), None);
+    assert_eq!(Pow::DEFAULT.apply(big(3), big(41_350)), None);
+    assert_eq!(
+        Pow::new(1 << 20).apply(big(2), big(100_000)),
+        Some(big(1) << 100_000)
+    );
+    assert_eq!(Pow::new(10).apply(big(2), big(9)), Some(big(512)));
+    assert_eq!(Pow::new(10).apply(big(2), big(10)), None);
+    assert_eq!(Pow::DEFAULT.apply(big(2), big(1000)), Some(big(1) << 1000));
+    assert_eq!(Pow::DEFAULT.apply(big(1), big(1 << 40)), Some(big(1)));
+    assert_eq!(
+        Pow::DEFAULT.unapply(big(1) << 1000, big(1000)),
+        Inverse::Lhs(big(2))
+    );
+    assert_eq!(
+        Pow::DEFAULT.unapply(big(5), big(1 << 40)),
+        Inverse::Impossible
+    );
+    assert_eq!(
+        Pow::DEFAULT.unapply(big(1), big(1 << 40)),
+        Inverse::Lhs(big(1))
+    );
+
+    assert_eq!(Mul.apply(usize::MAX, 2), None);
+    assert_eq!(Add.apply(usize::MAX, 1), None);
+    let combine = Combine::DECIMAL;
+    assert_eq!(
+        combine.apply(1844674407370955161_usize, 5),
+        Some(usize::MAX)
+    );
+    assert_eq!(combine.apply(1844674407370955161_usize, 6), None);
+    assert_eq!(combine.apply(1_usize, usize::MAX), None);
+    assert_eq!(combine.unapply(5_usize, usize::MAX), Inverse::Impossible);
+    assert_eq!(Pow::DEFAULT.apply(2_usize, 64), None);
+    assert_eq!(Pow::DEFAULT.unapply(usize::MAX, 2), Inverse::Impossible);
+
+    // 2^32 * 2^32 wraps around to 0 in 64 bits, which must not count as a solution
+    let equations = read_equations::<usize>("0: 4294967296 4294967296\n18446744073709551615: 1844674407370955161 5\n0: 1844674407370955161 6 0");
+    assert!(!can_be_true(&equations[0], OPERATORS_PART2));
+    assert!(!equation_is_true(&equations[0], OPERATORS_PART2));
+    assert_eq!(
+        render_solution(
+            &equations[1],
+            &solve(&equations[1], OPERATORS_PART2).unwrap()
+        ),
+        "1844674407370955161 || 5 = 18446744073709551615"
+    );
+    assert_eq!(solve_all(&equations[2], OPERATORS_PART2).len(), 2);
+
+    let equations = read_equations::<u128>(
+        "18446744073709551616: 4294967296 4294967296\n5: 18446744073709551616 18446744073709551616",
+    );
+    let operators = operator_set::<u128>("* + ||").unwrap();
+    let operators = operators.iter().map(Box::as_ref).collect::<Vec<_>>();
+    assert!(can_be_true(&equations[0], &operators));
+    assert!(!can_be_true(&equations[1], &operators));
+    assert_eq!(total_calibration_result(&equations, &operators), 1 << 64);
+
+    let equations = read_equations::<BigUint>("340282366920938463463374607431768211456: 18446744073709551616 18446744073709551616\n1844674407370955161618446744073709551616: 18446744073709551616 18446744073709551616");
+    let operators = operator_set::<BigUint>("* + ||").unwrap();
+    let operators = operators.iter().map(Box::as_ref).collect::<Vec<_>>();
+    assert_eq!(
+        render_solution(&equations[0], &solve(&equations[0], &operators).unwrap()),
+        "18446744073709551616 * 18446744073709551616 = 340282366920938463463374607431768211456"
+    );
+    assert_eq!(
+        render_solution(&equations[1], &solve(&equations[1], &operators).unwrap()),
+        "18446744073709551616 || 18446744073709551616 = 1844674407370955161618446744073709551616"
+    );
+}
+
+#[test]
+fn test_evaluation() {
+    let values = [2_usize, 3, 4];
+    assert_eq!(evaluate_precedence(&values, &[&Add, &Mul]), Some(14));
+    assert_eq!(evaluate_precedence(&values, &[&Mul, &Add]), Some(10));
+    assert_eq!(evaluate_precedence(&values, &[&Sub, &Mul]), None);
+    assert_eq!(
+        evaluate_precedence(&[2_usize, 3, 2], &[&Pow::DEFAULT, &Pow::DEFAULT]),
+        Some(512)
+    );
+    assert_eq!(
+        evaluate_precedence(&[20_usize, 3, 2], &[&Sub, &Sub]),
+        Some(15)
+    );
+    assert_eq!(
+        evaluate_precedence(
+            &[1_usize, 2, 3, 4, 5],
+            &[&Add, &Mul, &Combine::DECIMAL, &Pow::DEFAULT]
+        ),
+        Some(1 + 2 * 34usize.pow(5))
+    );
+    assert_eq!(
+        Evaluation::AnyParenthesization.results(&values, &[&Add, &Mul]),
+        vec![14, 20]
+    );
+
+    let equation = Equation {
+        result: 14,
+        values: values.to_vec(),
+    };
+    let counts = [
+        Evaluation::LeftToRight,
+        Evaluation::Precedence,
+        Evaluation::AnyParenthesization,
+    ]
+    .map(|evaluation| count_solutions_with(&equation, OPERATORS_PART1, evaluation));
+    assert_eq!(counts, [0, 1, 2]);
+    assert_eq!(
+        render_parenthesized(&equation, &[&Add, &Mul]).unwrap(),
+        "2 + (3 * 4) = 14"
+    );
+    assert_eq!(render_parenthesized(&equation, &[&Mul, &Mul]), None);
+
+    let equations = read_equations(
+        r#"
 190: 10 19
 3267: 81 40 27
 83: 17 5
+292: 11 6 16 20"#
+            .trim(),
+    );
+    let counts = |evaluation| {
+        equations
+            .iter()
+            .map(|e| count_solutions_with(e, OPERATORS_PART1, evaluation))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(counts(Evaluation::LeftToRight), vec![1, 2, 0, 1]);
+    assert_eq!(counts(Evaluation::Precedence), vec![1, 1, 0, 0]);
+    assert_eq!(counts(Evaluation::AnyParenthesization), vec![1, 2, 0, 1]);
+    let solution = &solutions_with(
+        &equations[3],
+        OPERATORS_PART1,
+        Evaluation::AnyParenthesization,
+    )[0];
+    assert_eq!(
+        render_parenthesized(&equations[3], solution).unwrap(),
+        "((11 + 6) * 16) + 20 = 292"
+    );
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = r#"190: 10 19
+3267: 81 40 27
+83: 17 5
 156: 15 6
 7290: 6 8 6 15
 161011: 16 10 13
 192: 17 8 14
 21037: 9 7 18 13
-292: 11 6 16 20"#
-        .trim();
+292: 11 6 16 20"#;
+
+#[test]
+fn test_count_solutions() {
+    for operators in [OPERATORS_PART1, OPERATORS_PART2] {
+        for (equation, brute_force) in brute_force_equations(&[0, 1, 2, 3, 10], 40, operators) {
+            assert_eq!(
+                count_solutions(&equation, operators),
+                brute_force,
+                "{:?}",
+                equation
+            );
+        }
+    }
+    // all 2^20 ways to put + and * between 21 zeros work, too many to list them all
+    let equation = Equation {
+        result: 0,
+        values: vec![0; 21],
+    };
+    assert_eq!(count_solutions(&equation, OPERATORS_PART1), 1 << 20);
+
+    let equations1 = read_equations(EXAMPLE);
+    assert_eq!(equations1[1].to_string(), "3267: 81 40 27");
+    assert_eq!(
+        ambiguity_table(&equations1, OPERATORS_PART2),
+        r#"
+solutions  equation          example
+2          3267: 81 40 27    81 + 40 * 27 = 3267
+1          190: 10 19        10 * 19 = 190
+1          156: 15 6         15 || 6 = 156
+1          7290: 6 8 6 15    6 * 8 || 6 * 15 = 7290
+1          192: 17 8 14      17 || 8 + 14 = 192
+1          292: 11 6 16 20   11 + 6 * 16 + 20 = 292
+0          83: 17 5          -
+0          161011: 16 10 13  -
+0          21037: 9 7 18 13  -
+"#
+        .trim()
+    );
+}
+
+#[test]
+fn test_par_calibrate() {
+    let equations1 = read_equations(EXAMPLE);
+    let reported = std::sync::Mutex::new(Vec::new());
+    let calibration = par_calibrate(&equations1, OPERATORS_PART2, |done, total| {
+        reported.lock().unwrap().push((done, total))
+    });
+    assert_eq!(calibration.total, 11387);
+    assert_eq!(
+        calibration
+            .equations
+            .iter()
+            .map(|c| c.solvable)
+            .collect::<Vec<_>>(),
+        vec![true, true, false, true, true, false, true, false, true]
+    );
+    let mut reported = reported.into_inner().unwrap();
+    reported.sort();
+    assert_eq!(reported, (1..=9).map(|done| (done, 9)).collect::<Vec<_>>());
+
+    let data2 = std::fs::read_to_string("input/day7").unwrap();
+    let equations2 = read_equations(&data2);
+    assert_eq!(
+        par_total_calibration_result(&equations2, OPERATORS_PART2),
+        492383931650959
+    );
+}
+
+#[test]
+fn day() {
+    let data1 = EXAMPLE;
 
     let equations1 = read_equations(data1);
 
@@ -135,6 +1256,12 @@ fn day() {
 
     let data2 = std::fs::read_to_string("input/day7").unwrap();
     let equations2 = read_equations(&data2);
+    for equation in &equations2 {
+        assert_eq!(
+            can_be_true(equation, OPERATORS_PART1),
+            equation_is_true(equation, OPERATORS_PART1)
+        );
+    }
     assert_eq!(
         total_calibration_result(&equations2, OPERATORS_PART1),
         5837374519342
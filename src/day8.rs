@@ -1,4 +1,7 @@
+use anyhow::{bail, Result};
 use itertools::Itertools;
+use num_rational::Ratio;
+use std::collections::{BTreeSet, HashMap};
 
 #[derive(Default)]
 pub struct Game {
@@ -29,6 +32,14 @@ impl Vec2 {
     pub fn translate(&self, rhs: Vec2) -> Vec2 {
         Vec2::new(self.x - rhs.x, self.y - rhs.y)
     }
+    pub fn negate(&self) -> Vec2 {
+        Vec2::new(-self.x, -self.y)
+    }
+    /// The smallest step in the same direction that still lands on whole coordinates.
+    pub fn reduce(&self) -> Vec2 {
+        let gcd = num_integer::gcd(self.x, self.y).max(1);
+        Vec2::new(self.x / gcd, self.y / gcd)
+    }
 }
 
 impl Game {
@@ -61,13 +72,16 @@ pub fn generate_antinodes_for_antenna_pair(
     resonance: bool,
 ) -> Vec<Vec2> {
     let delta = lhs.pos.delta(rhs.pos);
-    let mut antinodes = vec![lhs.pos.translate(delta)];
-    if resonance {
-        antinodes.extend([lhs.pos, rhs.pos]);
-        let mut pos = antinodes[0];
+    if !resonance {
+        return vec![lhs.pos.translate(delta), rhs.pos.translate(delta.negate())];
+    }
+    // every grid point on the line counts, including the ones between steps of the full delta
+    let mut antinodes = Vec::new();
+    for step in [delta.reduce(), delta.reduce().negate()] {
+        let mut pos = lhs.pos;
         while game.inside_boundaries(pos) {
-            pos = pos.translate(delta);
             antinodes.push(pos);
+            pos = pos.translate(step);
         }
     }
     antinodes
@@ -76,7 +90,7 @@ pub fn generate_antinodes_for_antenna_pair(
 pub fn get_antinodes(game: &Game, resonance: bool) -> Vec<Vec2> {
     game.antennas
         .iter()
-        .permutations(2)
+        .combinations(2)
         .filter(|pair| pair[0].freq == pair[1].freq)
         .flat_map(|pair| generate_antinodes_for_antenna_pair(game, pair[0], pair[1], resonance))
         .filter(|pos| game.inside_boundaries(*pos))
@@ -84,10 +98,298 @@ pub fn get_antinodes(game: &Game, resonance: bool) -> Vec<Vec2> {
         .collect()
 }
 
-#[test]
-fn day() {
-    let data1 = r#"
-............
+/// Where antinodes go on the line through two antennas of the same frequency: wherever one
+/// antenna is `ratio` times as far away as the other.
+pub struct Harmonics {
+    ratios: Vec<Ratio<isize>>,
+    between: bool,
+}
+
+impl Harmonics {
+    /// `ratios` as `(numerator, denominator)`, so the puzzle is `&[(2, 1)]`.
+    pub fn new(ratios: &[(isize, isize)], between: bool) -> Result<Harmonics> {
+        let ratios = ratios
+            .iter()
+            .map(|&(numerator, denominator)| {
+                if numerator <= 0 || denominator <= 0 {
+                    bail!("ratio {}:{} is not positive", numerator, denominator);
+                }
+                Ok(Ratio::new(numerator, denominator))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Harmonics { ratios, between })
+    }
+
+    pub fn ratios(&self) -> &[Ratio<isize>] {
+        &self.ratios
+    }
+
+    /// Also look for antinodes between the two antennas, not just beyond them.
+    pub fn between(&self) -> bool {
+        self.between
+    }
+
+    /// Where the antinodes are on the line, as the fraction of the way from `lhs` to `rhs`.
+    /// Being `r` times as far from `lhs` as from `rhs` means `|t| = r |t - 1|`, which has one
+    /// solution beyond the antennas and one between them, and the same again for `1 / r`.
+    fn positions(&self) -> Vec<Ratio<isize>> {
+        let one = Ratio::from_integer(1);
+        let mut positions = Vec::new();
+        for &ratio in &self.ratios {
+            for ratio in [ratio, ratio.recip()] {
+                if ratio != one {
+                    positions.push(ratio / (ratio - one));
+                }
+                if self.between {
+                    positions.push(ratio / (ratio + one));
+                }
+            }
+        }
+        positions.into_iter().unique().collect()
+    }
+}
+
+/// Only the antinodes that fall on whole coordinates.
+pub fn generate_antinodes_with_harmonics(
+    lhs: &Antenna,
+    rhs: &Antenna,
+    harmonics: &Harmonics,
+) -> Vec<Vec2> {
+    let delta = lhs.pos.delta(rhs.pos);
+    harmonics
+        .positions()
+        .into_iter()
+        .filter_map(|t| {
+            let x = Ratio::from_integer(lhs.pos.x) + t * delta.x;
+            let y = Ratio::from_integer(lhs.pos.y) + t * delta.y;
+            (x.is_integer() && y.is_integer()).then(|| Vec2::new(x.to_integer(), y.to_integer()))
+        })
+        .collect()
+}
+
+pub fn get_antinodes_with_harmonics(game: &Game, harmonics: &Harmonics) -> Vec<Vec2> {
+    game.antennas
+        .iter()
+        .tuple_combinations()
+        .filter(|(lhs, rhs)| lhs.freq == rhs.freq)
+        .flat_map(|(lhs, rhs)| generate_antinodes_with_harmonics(lhs, rhs, harmonics))
+        .filter(|pos| game.inside_boundaries(*pos))
+        .unique()
+        .collect()
+}
+
+/// A game whose antennas can be added and removed one at a time, keeping count of how many pairs
+/// of antennas put an antinode on each position instead of starting over.
+pub struct AntinodeCounts {
+    game: Game,
+    resonance: bool,
+    counts: HashMap<Vec2, usize>,
+}
+
+impl AntinodeCounts {
+    pub fn new(game: Game, resonance: bool) -> AntinodeCounts {
+        let mut counts = AntinodeCounts {
+            game: Game {
+                antennas: Vec::new(),
+                ..game
+            },
+            resonance,
+            counts: HashMap::new(),
+        };
+        for antenna in game.antennas {
+            counts.insert(antenna);
+        }
+        counts
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// The antinodes `antenna` makes together with the other antennas of its frequency.
+    fn pair_antinodes<'a>(&'a self, antenna: &'a Antenna) -> impl Iterator<Item = Vec2> + 'a {
+        self.game
+            .antennas
+            .iter()
+            .filter(|other| other.freq == antenna.freq && other.pos != antenna.pos)
+            .flat_map(move |other| {
+                generate_antinodes_for_antenna_pair(&self.game, antenna, other, self.resonance)
+                    .into_iter()
+                    .filter(|pos| self.game.inside_boundaries(*pos))
+                    .unique()
+            })
+    }
+
+    fn insert(&mut self, antenna: Antenna) {
+        for pos in self.pair_antinodes(&antenna).collect_vec() {
+            *self.counts.entry(pos).or_default() += 1;
+        }
+        self.game.antennas.push(antenna);
+    }
+
+    pub fn add(&mut self, antenna: Antenna) -> Result<()> {
+        if !self.game.inside_boundaries(antenna.pos) {
+            bail!("{:?} is outside the map", antenna.pos);
+        }
+        if self.game.antennas.iter().any(|a| a.pos == antenna.pos) {
+            bail!("there is already an antenna at {:?}", antenna.pos);
+        }
+        self.insert(antenna);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, pos: Vec2) -> Result<Antenna> {
+        let Some(index) = self.game.antennas.iter().position(|a| a.pos == pos) else {
+            bail!("there is no antenna at {:?}", pos);
+        };
+        let antenna = self.game.antennas.swap_remove(index);
+        for pos in self.pair_antinodes(&antenna).collect_vec() {
+            let count = self.counts.get_mut(&pos).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&pos);
+            }
+        }
+        Ok(antenna)
+    }
+
+    /// How many pairs of antennas have an antinode at `pos`.
+    pub fn pairs_at(&self, pos: Vec2) -> usize {
+        self.counts.get(&pos).copied().unwrap_or(0)
+    }
+
+    pub fn antinodes(&self) -> impl Iterator<Item = Vec2> + '_ {
+        self.counts.keys().copied()
+    }
+
+    /// The number of positions with an antinode.
+    pub fn covered(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// How many positions would get their first antinode if `antenna` was added.
+    pub fn gain(&self, antenna: &Antenna) -> usize {
+        self.pair_antinodes(antenna)
+            .filter(|pos| !self.counts.contains_key(pos))
+            .unique()
+            .count()
+    }
+
+    /// The free position where one more antenna of `freq` adds the most antinode positions, with
+    /// how many it adds. Ties go to the first position in reading order.
+    pub fn best_placement(&self, freq: char) -> Option<(Vec2, usize)> {
+        (0..self.game.height)
+            .cartesian_product(0..self.game.width)
+            .map(|(y, x)| Vec2::new(x, y))
+            .filter(|&pos| self.game.antennas.iter().all(|a| a.pos != pos))
+            .map(|pos| (pos, self.gain(&Antenna { pos, freq })))
+            .min_by_key(|&(_, gain)| std::cmp::Reverse(gain))
+    }
+}
+
+/// Which frequencies put an antinode on each position.
+pub fn antinode_frequencies(game: &Game, resonance: bool) -> HashMap<Vec2, BTreeSet<char>> {
+    let mut frequencies: HashMap<Vec2, BTreeSet<char>> = HashMap::new();
+    for (lhs, rhs) in game
+        .antennas
+        .iter()
+        .tuple_combinations()
+        .filter(|(lhs, rhs)| lhs.freq == rhs.freq)
+    {
+        for pos in generate_antinodes_for_antenna_pair(game, lhs, rhs, resonance) {
+            if game.inside_boundaries(pos) {
+                frequencies.entry(pos).or_default().insert(lhs.freq);
+            }
+        }
+    }
+    frequencies
+}
+
+/// Spreads the frequencies of the game over a fixed palette, in sorted order, so up to 12 of them
+/// get colours of their own.
+fn frequency_colours(game: &Game) -> HashMap<char, [u8; 3]> {
+    const PALETTE: [[u8; 3]; 12] = [
+        [230, 60, 60],
+        [60, 140, 230],
+        [90, 200, 90],
+        [240, 180, 30],
+        [180, 90, 220],
+        [40, 200, 200],
+        [240, 120, 40],
+        [230, 90, 170],
+        [150, 200, 40],
+        [120, 120, 240],
+        [200, 150, 100],
+        [100, 220, 150],
+    ];
+    game.antennas
+        .iter()
+        .map(|a| a.freq)
+        .sorted()
+        .dedup()
+        .zip(PALETTE.iter().cycle())
+        .map(|(freq, &colour)| (freq, colour))
+        .collect()
+}
+
+/// The map as in the puzzle, with antennas on top of the `#` antinodes. With `colour`, antennas
+/// and the antinodes of a single frequency get that frequency's colour as terminal escapes.
+pub fn render(game: &Game, resonance: bool, colour: bool) -> String {
+    let antinodes = antinode_frequencies(game, resonance);
+    let colours = frequency_colours(game);
+    let paint = |c: char, freq: Option<char>| match freq {
+        Some(freq) if colour => {
+            let [r, g, b] = colours[&freq];
+            format!("\x1b[38;2;{};{};{}m{}\x1b[0m", r, g, b, c)
+        }
+        _ => c.to_string(),
+    };
+    (0..game.height)
+        .map(|y| {
+            (0..game.width)
+                .map(|x| {
+                    let pos = Vec2::new(x, y);
+                    if let Some(antenna) = game.antennas.iter().find(|a| a.pos == pos) {
+                        return paint(antenna.freq, Some(antenna.freq));
+                    }
+                    match antinodes.get(&pos) {
+                        Some(freqs) => paint('#', freqs.iter().exactly_one().ok().copied()),
+                        None => paint('.', None),
+                    }
+                })
+                .collect::<String>()
+        })
+        .join("\n")
+}
+
+/// Antennas in their frequency's colour, antinodes in a darker shade of it, or grey when several
+/// frequencies share the position.
+pub fn render_ppm(game: &Game, resonance: bool, scale: usize) -> Vec<u8> {
+    let antinodes = antinode_frequencies(game, resonance);
+    let colours = frequency_colours(game);
+    let colour = |x: isize, y: isize| -> [u8; 3] {
+        let pos = Vec2::new(x, y);
+        if let Some(antenna) = game.antennas.iter().find(|a| a.pos == pos) {
+            return colours[&antenna.freq];
+        }
+        match antinodes.get(&pos).map(|freqs| freqs.iter().exactly_one()) {
+            Some(Ok(freq)) => colours[freq].map(|c| c / 2),
+            Some(Err(_)) => [140, 140, 140],
+            None => [20, 20, 20],
+        }
+    };
+    let (width, height) = (game.width as usize, game.height as usize);
+    let mut image = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            image.extend(colour((x / scale) as isize, (y / scale) as isize));
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = r#"............
 ........0...
 .....0......
 .......0....
@@ -98,9 +400,240 @@ fn day() {
 ........A...
 .........A..
 ............
-............
+............"#;
+
+#[test]
+fn test_render() {
+    let data = EXAMPLE;
+    let game = read_game(data);
+    assert_eq!(
+        render(&game, false, false),
+        r#"
+......#....#
+...#....0...
+....#0....#.
+..#....0....
+....0....#..
+.#....A.....
+...#........
+#......#....
+........A...
+.........A..
+..........#.
+..........#.
+"#
+        .trim()
+    );
+    assert_eq!(
+        render(&game, true, false),
+        r#"
+##....#....#
+.#.#....0...
+..#.#0....#.
+..##...0....
+....0....#..
+.#...#A....#
+...#..#.....
+#....#.#....
+..#.....A...
+....#....A..
+.#........#.
+...#......##
+"#
+        .trim()
+    );
+
+    let coloured = render(&game, false, true);
+    let first_row = coloured.lines().next().unwrap();
+    assert_eq!(
+        first_row,
+        "......\x1b[38;2;230;60;60m#\x1b[0m....\x1b[38;2;230;60;60m#\x1b[0m"
+    );
+    // '0' comes before 'A', so it gets the first colour
+    assert!(coloured.contains("\x1b[38;2;230;60;60m0\x1b[0m"));
+    assert!(coloured.contains("\x1b[38;2;60;140;230mA\x1b[0m"));
+
+    let image = render_ppm(&game, true, 2);
+    let header = b"P6\n24 24\n255\n";
+    assert_eq!(&image[..header.len()], header);
+    assert_eq!(image.len(), header.len() + 24 * 24 * 3);
+    let pixel = |x: usize, y: usize| {
+        let offset = header.len() + (y * 24 + x) * 3;
+        [image[offset], image[offset + 1], image[offset + 2]]
+    };
+    assert_eq!(pixel(17, 3), [230, 60, 60]);
+    assert_eq!(pixel(0, 0), [30, 70, 115]);
+    assert_eq!(pixel(4, 0), [20, 20, 20]);
+    assert_eq!(pixel(22, 1), [115, 30, 30]);
+}
+
+#[test]
+fn test_antinode_counts() {
+    let data = EXAMPLE;
+    for resonance in [false, true] {
+        let mut counts = AntinodeCounts::new(read_game(data), resonance);
+        assert_eq!(counts.covered(), [14, 34][resonance as usize]);
+
+        let removed = counts.remove(Vec2::new(6, 5)).unwrap();
+        assert_eq!(removed.freq, 'A');
+        assert!(counts.remove(Vec2::new(6, 5)).is_err());
+        let expected = get_antinodes(counts.game(), resonance);
+        assert_eq!(counts.covered(), expected.len());
+        assert!(expected.iter().all(|&pos| counts.pairs_at(pos) > 0));
+
+        counts.add(removed).unwrap();
+        assert_eq!(counts.covered(), [14, 34][resonance as usize]);
+        assert!(counts
+            .add(Antenna {
+                pos: Vec2::new(6, 5),
+                freq: 'B'
+            })
+            .is_err());
+        assert!(counts
+            .add(Antenna {
+                pos: Vec2::new(12, 0),
+                freq: 'B'
+            })
+            .is_err());
+
+        // adding the best placement for real gives what it promised, and nothing does better
+        let (pos, gain) = counts.best_placement('0').unwrap();
+        let before = counts.covered();
+        for y in 0..counts.game().height {
+            for x in 0..counts.game().width {
+                let antenna = Antenna {
+                    pos: Vec2::new(x, y),
+                    freq: '0',
+                };
+                if counts.add(antenna).is_ok() {
+                    assert!(counts.covered() - before <= gain);
+                    counts.remove(Vec2::new(x, y)).unwrap();
+                }
+            }
+        }
+        counts.add(Antenna { pos, freq: '0' }).unwrap();
+        assert_eq!(counts.covered(), before + gain);
+        assert_eq!(
+            counts.covered(),
+            get_antinodes(counts.game(), resonance).len()
+        );
+    }
+}
+
+#[test]
+fn test_harmonics() {
+    let sorted = |mut antinodes: Vec<Vec2>| {
+        antinodes.sort_by_key(|pos| (pos.y, pos.x));
+        antinodes
+    };
+    let puzzle = Harmonics::new(&[(2, 1)], false).unwrap();
+    assert_eq!(puzzle.ratios(), &[Ratio::from_integer(2)]);
+    assert!(!puzzle.between());
+    let data = std::fs::read_to_string("input/day8").unwrap();
+    let game = read_game(&data);
+    assert_eq!(
+        sorted(get_antinodes_with_harmonics(&game, &puzzle)),
+        sorted(get_antinodes(&game, false))
+    );
+
+    let data = r#"
+..........
+.a........
+..........
+..........
+....a.....
+..........
+..........
+..........
+..........
+..........
+"#
+    .trim();
+    let game = read_game(data);
+    let pair = (&game.antennas[0], &game.antennas[1]);
+    let antinodes = |ratios: &[(isize, isize)], between| {
+        let harmonics = Harmonics::new(ratios, between).unwrap();
+        sorted(generate_antinodes_with_harmonics(
+            pair.0, pair.1, &harmonics,
+        ))
+    };
+    assert_eq!(
+        antinodes(&[(2, 1)], false),
+        vec![Vec2::new(-2, -2), Vec2::new(7, 7)]
+    );
+    // between the antennas, a third of the way from either one
+    assert_eq!(
+        antinodes(&[(2, 1)], true),
+        vec![
+            Vec2::new(-2, -2),
+            Vec2::new(2, 2),
+            Vec2::new(3, 3),
+            Vec2::new(7, 7)
+        ]
+    );
+    // 3:2 is at 3 and -2 times the distance, and in between at 3/5 and 2/5, which are not whole
+    assert_eq!(
+        antinodes(&[(3, 2)], true),
+        vec![Vec2::new(-5, -5), Vec2::new(10, 10)]
+    );
+    assert_eq!(antinodes(&[(1, 1)], true), vec![]);
+    // 3:1 falls between cells, 4:2 is the same as 2:1
+    assert_eq!(
+        antinodes(&[(3, 1), (4, 2)], false),
+        vec![Vec2::new(-2, -2), Vec2::new(7, 7)]
+    );
+    assert!(Harmonics::new(&[(0, 1)], false).is_err());
+    assert!(Harmonics::new(&[(1, -2)], false).is_err());
+}
+
+#[test]
+fn test_resonant_harmonics() {
+    // the antennas are two steps of (1, 2) apart, so (1, 2) is in line with them as well
+    let data = r#"
+a..
+...
+...
+...
+..a
+...
+...
+...
+...
 "#
     .trim();
+    let game = read_game(data);
+    let mut antinodes = get_antinodes(&game, true);
+    antinodes.sort_by_key(|pos| (pos.y, pos.x));
+    assert_eq!(
+        antinodes,
+        vec![Vec2::new(0, 0), Vec2::new(1, 2), Vec2::new(2, 4)]
+    );
+    assert_eq!(Vec2::new(-4, 6).reduce(), Vec2::new(-2, 3));
+    assert_eq!(Vec2::new(0, -3).reduce(), Vec2::new(0, -1));
+
+    // every cell in line with two antennas of the same frequency, straight from the definition
+    let data = std::fs::read_to_string("input/day8").unwrap();
+    let game = read_game(&data);
+    let in_line = (0..game.height)
+        .cartesian_product(0..game.width)
+        .filter(|&(y, x)| {
+            game.antennas
+                .iter()
+                .tuple_combinations()
+                .filter(|(lhs, rhs)| lhs.freq == rhs.freq)
+                .any(|(lhs, rhs)| {
+                    let delta = lhs.pos.delta(rhs.pos);
+                    let to_cell = lhs.pos.delta(Vec2::new(x, y));
+                    delta.x * to_cell.y == delta.y * to_cell.x
+                })
+        })
+        .count();
+    assert_eq!(get_antinodes(&game, true).len(), in_line);
+}
+
+#[test]
+fn day() {
+    let data1 = EXAMPLE;
     let game = read_game(data1);
     assert_eq!(game.antennas.len(), 7);
     assert_eq!(game.width, 12);